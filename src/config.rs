@@ -0,0 +1,1504 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use tonic::transport::Uri;
+use url::Url;
+
+use crate::integrity::{ManifestKey, Sha256Digest};
+use crate::proxy::{ProxyConfig, ProxyMode};
+use crate::tls::{SpkiPin, TlsPolicy};
+
+/// Errors raised while validating a [`RecordingConfig`].
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("missing required field `{0}`")]
+    MissingField(&'static str),
+
+    #[error("field `user_id` must not be empty")]
+    EmptyUserId,
+
+    #[error("invalid URL for `{field}` ({value}): {source}")]
+    InvalidUrl {
+        field: &'static str,
+        value: String,
+        source: url::ParseError,
+    },
+
+    #[error("unsupported scheme `{scheme}` for `{field}`, expected http or https")]
+    UnsupportedScheme { field: &'static str, scheme: String },
+
+    #[error("invalid gRPC endpoint `{value}`: {reason}")]
+    InvalidEndpoint { value: String, reason: String },
+
+    #[error("invalid resolution `{0}`, expected WIDTHxHEIGHT with non-zero sides")]
+    InvalidResolution(String),
+
+    #[error("unsupported container `{0}`, expected one of webm, mp4, avi, mkv")]
+    InvalidContainer(String),
+
+    #[error("unknown recorder backend `{0}`, expected external or native")]
+    InvalidRecorderBackend(String),
+
+    #[error("the {backend} recorder cannot write {container} files")]
+    UnsupportedContainer {
+        backend: RecorderBackend,
+        container: Container,
+    },
+
+    #[error("display must be primary, all, an index or a name")]
+    InvalidDisplay,
+
+    #[error("unknown display layout `{0}`, expected separate or composite")]
+    InvalidDisplayLayout(String),
+
+    #[error("the {0} recorder can only capture the primary display")]
+    UnsupportedDisplay(RecorderBackend),
+
+    #[error("unknown capture source `{0}`, expected display, test-pattern or raw:<path>")]
+    InvalidCaptureSource(String),
+
+    #[error("the {0} recorder can only capture real displays")]
+    UnsupportedCaptureSource(RecorderBackend),
+
+    #[error("field `{field}` expects a number, got `{value}`")]
+    InvalidNumber { field: &'static str, value: String },
+
+    #[error("field `{field}` expects true or false, got `{value}`")]
+    InvalidBool { field: &'static str, value: String },
+
+    #[error("field `{0}` must be greater than zero")]
+    Zero(&'static str),
+
+    #[error("{0} requires an https endpoint")]
+    TlsRequiresHttps(&'static str),
+
+    #[error("`insecure_tls` would ignore the configured `{0}`; remove one of them")]
+    InsecurePins(&'static str),
+
+    #[error("field `{field}` expects public key pins like `sha256/<base64>`, got `{value}`")]
+    InvalidPin { field: &'static str, value: String },
+
+    #[error("field `recorder_sha256` expects 64 hex digits, got `{0}`")]
+    InvalidDigest(String),
+
+    #[error("field `recorder_public_key` expects a base64 Ed25519 public key, got `{0}`")]
+    InvalidPublicKey(String),
+
+    #[error("field `proxy` expects system, none or an http:// proxy URL, got `{0}`")]
+    InvalidProxy(String),
+
+    #[error("`proxy_username` and `proxy_password` must be set together")]
+    IncompleteProxyAuth,
+
+    #[error("`grpc_client_cert` and `grpc_client_key` must be set together")]
+    IncompleteClientIdentity,
+
+    #[error("upload chunks of {chunk} bytes do not fit in gRPC messages of {max} bytes")]
+    ChunkTooLarge { chunk: usize, max: usize },
+}
+
+/// TLS settings for an `https` gRPC endpoint. The system roots are always
+/// trusted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GrpcTls {
+    /// Additional PEM CA bundle, e.g. for a private CA.
+    pub ca_cert: Option<PathBuf>,
+    /// Name the server certificate is checked against instead of the
+    /// endpoint host, for servers addressed by IP.
+    pub server_name: Option<String>,
+    /// PEM client certificate and private key for mutual TLS.
+    pub client_identity: Option<(PathBuf, PathBuf)>,
+}
+
+/// Bytes a gRPC upload message needs on top of the chunk data.
+const MESSAGE_OVERHEAD: usize = 1024;
+
+/// Capture resolution passed to the recorder as `WIDTHxHEIGHT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Resolution {
+    pub const HD: Resolution = Resolution {
+        width: 1280,
+        height: 720,
+    };
+
+    pub fn new(width: u32, height: u32) -> Result<Self, ConfigError> {
+        if width == 0 || height == 0 {
+            return Err(ConfigError::InvalidResolution(format!(
+                "{}x{}",
+                width, height
+            )));
+        }
+        Ok(Self { width, height })
+    }
+}
+
+impl Default for Resolution {
+    fn default() -> Self {
+        Self::HD
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ConfigError::InvalidResolution(s.to_string());
+        let (w, h) = s.trim().split_once(['x', 'X']).ok_or_else(invalid)?;
+        let width = w.trim().parse().map_err(|_| invalid())?;
+        let height = h.trim().parse().map_err(|_| invalid())?;
+        Resolution::new(width, height).map_err(|_| invalid())
+    }
+}
+
+/// Container format the recorder is asked to produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Container {
+    #[default]
+    Webm,
+    Mp4,
+    Avi,
+    Mkv,
+}
+
+impl Container {
+    pub const ALL: [Container; 4] = [
+        Container::Webm,
+        Container::Mp4,
+        Container::Avi,
+        Container::Mkv,
+    ];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Container::Webm => "webm",
+            Container::Mp4 => "mp4",
+            Container::Avi => "avi",
+            Container::Mkv => "mkv",
+        }
+    }
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+impl FromStr for Container {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ext = s.trim().trim_start_matches('.');
+        Container::ALL
+            .into_iter()
+            .find(|c| c.extension().eq_ignore_ascii_case(ext))
+            .ok_or_else(|| ConfigError::InvalidContainer(s.to_string()))
+    }
+}
+
+/// Which [`Recorder`](crate::recorder::Recorder) captures the segments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum RecorderBackend {
+    /// The downloaded `screen_record.exe`.
+    #[default]
+    External,
+    /// In-process capture with `scrap`, encoded by ffmpeg.
+    Native,
+}
+
+impl RecorderBackend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RecorderBackend::External => "external",
+            RecorderBackend::Native => "native",
+        }
+    }
+}
+
+impl fmt::Display for RecorderBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for RecorderBackend {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "external" => Ok(RecorderBackend::External),
+            "native" => Ok(RecorderBackend::Native),
+            _ => Err(ConfigError::InvalidRecorderBackend(s.to_string())),
+        }
+    }
+}
+
+/// Which displays the native recorder captures. Indices and names are the
+/// ones `list_displays` reports.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum DisplaySelection {
+    #[default]
+    Primary,
+    All,
+    Index(usize),
+    Name(String),
+}
+
+impl fmt::Display for DisplaySelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisplaySelection::Primary => f.write_str("primary"),
+            DisplaySelection::All => f.write_str("all"),
+            DisplaySelection::Index(index) => write!(f, "{}", index),
+            DisplaySelection::Name(name) => f.write_str(name),
+        }
+    }
+}
+
+impl FromStr for DisplaySelection {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(ConfigError::InvalidDisplay);
+        }
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "primary" => DisplaySelection::Primary,
+            "all" => DisplaySelection::All,
+            _ => match s.parse() {
+                Ok(index) => DisplaySelection::Index(index),
+                Err(_) => DisplaySelection::Name(s.to_string()),
+            },
+        })
+    }
+}
+
+/// Where the native recorder takes its frames from. The synthetic sources
+/// produce frames of the configured [`Resolution`] and need no display, for
+/// tests and development on headless machines.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum CaptureSourceKind {
+    /// The displays picked by [`DisplaySelection`].
+    #[default]
+    Display,
+    /// A moving test pattern stamped with the capture time.
+    TestPattern,
+    /// Raw BGRA frames replayed from a file, looping at its end.
+    RawFile(PathBuf),
+}
+
+impl fmt::Display for CaptureSourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureSourceKind::Display => f.write_str("display"),
+            CaptureSourceKind::TestPattern => f.write_str("test-pattern"),
+            CaptureSourceKind::RawFile(path) => write!(f, "raw:{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for CaptureSourceKind {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(path) = s.strip_prefix("raw:").filter(|path| !path.is_empty()) {
+            return Ok(CaptureSourceKind::RawFile(PathBuf::from(path)));
+        }
+        match s.to_ascii_lowercase().as_str() {
+            "display" => Ok(CaptureSourceKind::Display),
+            "test-pattern" | "test_pattern" => Ok(CaptureSourceKind::TestPattern),
+            _ => Err(ConfigError::InvalidCaptureSource(s.to_string())),
+        }
+    }
+}
+
+/// How several captured displays end up in the segment file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DisplayLayout {
+    /// One video track per display, recorded in lockstep.
+    Separate,
+    /// All displays side by side on one canvas.
+    #[default]
+    Composite,
+}
+
+impl fmt::Display for DisplayLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DisplayLayout::Separate => "separate",
+            DisplayLayout::Composite => "composite",
+        })
+    }
+}
+
+impl FromStr for DisplayLayout {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "separate" => Ok(DisplayLayout::Separate),
+            "composite" => Ok(DisplayLayout::Composite),
+            _ => Err(ConfigError::InvalidDisplayLayout(s.to_string())),
+        }
+    }
+}
+
+/// Validated settings for one recording session.
+///
+/// Build it with [`RecordingConfig::builder`]; every field is checked in
+/// [`RecordingConfigBuilder::build`] so a bad value is reported up front
+/// instead of failing halfway through a recording cycle.
+#[derive(Debug, Clone)]
+pub struct RecordingConfig {
+    user_id: String,
+    api_url: Url,
+    recorder_backend: RecorderBackend,
+    recorder_url: Option<Url>,
+    recorder_mirrors: Vec<Url>,
+    grpc_endpoint: Uri,
+    grpc_tls: Option<GrpcTls>,
+    insecure_tls: bool,
+    api_pins: Vec<SpkiPin>,
+    recorder_pins: Vec<SpkiPin>,
+    recorder_sha256: Option<Sha256Digest>,
+    recorder_public_key: Option<ManifestKey>,
+    allow_unverified_recorder: bool,
+    recorder_manifest_url: Option<Url>,
+    update_interval: Duration,
+    proxy: ProxyConfig,
+    segment_duration: Duration,
+    fps: NonZeroU32,
+    resolution: Resolution,
+    container: Container,
+    display: DisplaySelection,
+    display_layout: DisplayLayout,
+    capture_source: CaptureSourceKind,
+    upload_attempts: NonZeroU32,
+    retry_delay: Duration,
+    upload_workers: NonZeroU32,
+    stop_timeout: Duration,
+    upload_partial: bool,
+    watchdog_grace: Duration,
+    upload_chunk_size: usize,
+    connect_timeout: Duration,
+    upload_timeout: Duration,
+    keepalive: Option<Duration>,
+    max_message_size: usize,
+}
+
+impl RecordingConfig {
+    pub fn builder() -> RecordingConfigBuilder {
+        RecordingConfigBuilder::default()
+    }
+
+    pub fn user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    pub fn api_url(&self) -> &Url {
+        &self.api_url
+    }
+
+    pub fn recorder_backend(&self) -> RecorderBackend {
+        self.recorder_backend
+    }
+
+    /// Where the recorder executable is downloaded from; always set when
+    /// the backend downloads one.
+    pub fn recorder_url(&self) -> Option<&Url> {
+        self.recorder_url.as_ref()
+    }
+
+    /// Further copies of the recorder, tried in order after `recorder_url`.
+    pub fn recorder_mirrors(&self) -> &[Url] {
+        &self.recorder_mirrors
+    }
+
+    /// `recorder_url` followed by the mirrors.
+    pub fn recorder_urls(&self) -> impl Iterator<Item = &Url> {
+        self.recorder_url.iter().chain(&self.recorder_mirrors)
+    }
+
+    pub fn grpc_endpoint(&self) -> &Uri {
+        &self.grpc_endpoint
+    }
+
+    /// TLS settings; set exactly when the gRPC endpoint is `https`.
+    pub fn grpc_tls(&self) -> Option<&GrpcTls> {
+        self.grpc_tls.as_ref()
+    }
+
+    /// Certificate checks for the API.
+    pub fn api_tls(&self) -> TlsPolicy {
+        TlsPolicy {
+            insecure: self.insecure_tls,
+            pins: self.api_pins.clone(),
+        }
+    }
+
+    /// Certificate checks for the recorder download.
+    pub fn recorder_tls(&self) -> TlsPolicy {
+        TlsPolicy {
+            insecure: self.insecure_tls,
+            pins: self.recorder_pins.clone(),
+        }
+    }
+
+    /// Digest the recorder executable must have, overriding the manifest.
+    pub fn recorder_sha256(&self) -> Option<Sha256Digest> {
+        self.recorder_sha256
+    }
+
+    /// Key the recorder manifest must be signed with.
+    pub fn recorder_public_key(&self) -> Option<ManifestKey> {
+        self.recorder_public_key
+    }
+
+    /// Whether a downloaded recorder may run when there is neither a
+    /// `recorder_sha256` nor a manifest to check it against.
+    pub fn allow_unverified_recorder(&self) -> bool {
+        self.allow_unverified_recorder
+    }
+
+    /// Versioned recorder manifest; when set the recorder is installed and
+    /// kept up to date from it instead of downloaded from `recorder_url`.
+    pub fn recorder_manifest_url(&self) -> Option<&Url> {
+        self.recorder_manifest_url.as_ref()
+    }
+
+    /// How often the recorder manifest is checked for a new version.
+    pub fn update_interval(&self) -> Duration {
+        self.update_interval
+    }
+
+    /// Proxy for the API, the recorder download and the gRPC upload.
+    pub fn proxy(&self) -> &ProxyConfig {
+        &self.proxy
+    }
+
+    /// Host part of the gRPC endpoint (validated to be present).
+    pub fn grpc_host(&self) -> &str {
+        self.grpc_endpoint.host().unwrap_or_default()
+    }
+
+    /// Port of the gRPC endpoint (validated to be present).
+    pub fn grpc_port(&self) -> u16 {
+        self.grpc_endpoint.port_u16().unwrap_or_default()
+    }
+
+    pub fn segment_duration(&self) -> Duration {
+        self.segment_duration
+    }
+
+    pub fn fps(&self) -> NonZeroU32 {
+        self.fps
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    pub fn container(&self) -> Container {
+        self.container
+    }
+
+    pub fn display(&self) -> &DisplaySelection {
+        &self.display
+    }
+
+    pub fn display_layout(&self) -> DisplayLayout {
+        self.display_layout
+    }
+
+    pub fn capture_source(&self) -> &CaptureSourceKind {
+        &self.capture_source
+    }
+
+    pub fn upload_attempts(&self) -> NonZeroU32 {
+        self.upload_attempts
+    }
+
+    pub fn retry_delay(&self) -> Duration {
+        self.retry_delay
+    }
+
+    /// Number of segments uploaded concurrently in continuous mode.
+    pub fn upload_workers(&self) -> NonZeroU32 {
+        self.upload_workers
+    }
+
+    /// How long a cancelled recorder gets to exit after being asked to stop
+    /// before it is killed.
+    pub fn stop_timeout(&self) -> Duration {
+        self.stop_timeout
+    }
+
+    /// Whether a segment cut short by cancellation is still uploaded.
+    pub fn upload_partial(&self) -> bool {
+        self.upload_partial
+    }
+
+    /// Extra time past the segment duration before a recorder that is still
+    /// running is considered hung and killed.
+    pub fn watchdog_grace(&self) -> Duration {
+        self.watchdog_grace
+    }
+
+    /// Bytes of the file sent per gRPC message.
+    pub fn upload_chunk_size(&self) -> usize {
+        self.upload_chunk_size
+    }
+
+    pub fn connect_timeout(&self) -> Duration {
+        self.connect_timeout
+    }
+
+    /// Limit for a single upload RPC, i.e. for sending one segment.
+    pub fn upload_timeout(&self) -> Duration {
+        self.upload_timeout
+    }
+
+    /// HTTP/2 and TCP keepalive interval for the upload channel; `None`
+    /// disables keepalive pings.
+    pub fn keepalive(&self) -> Option<Duration> {
+        self.keepalive
+    }
+
+    /// Largest gRPC message sent or accepted, in bytes.
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+}
+
+/// Builder for [`RecordingConfig`].
+///
+/// `user_id`, `api_url` and the gRPC endpoint are always required.
+/// `recorder_url` is required only with `recorder_backend = external`, which
+/// downloads the recorder; the native backend does not need it.
+///
+/// Everything else defaults to the values the agent has always used (120 s
+/// segments at 24 fps, 1280x720, WebM, 3 upload attempts 5 s apart), with 2
+/// upload workers in continuous mode. A cancelled recorder gets 10 s to exit
+/// and its partial segment is uploaded; one still running 30 s past the
+/// segment duration is killed. Uploads go out in 1 MiB chunks over a channel
+/// with a 10 s connect timeout, a 10 min limit per segment, 30 s keepalive
+/// and 16 MiB messages.
+#[derive(Debug, Clone)]
+pub struct RecordingConfigBuilder {
+    user_id: Option<String>,
+    api_url: Option<String>,
+    recorder_backend: RecorderBackend,
+    recorder_url: Option<String>,
+    recorder_mirrors: Vec<String>,
+    grpc_endpoint: Option<String>,
+    grpc_ca_cert: Option<PathBuf>,
+    grpc_server_name: Option<String>,
+    grpc_client_cert: Option<PathBuf>,
+    grpc_client_key: Option<PathBuf>,
+    insecure_tls: bool,
+    api_pins: Vec<SpkiPin>,
+    recorder_pins: Vec<SpkiPin>,
+    recorder_sha256: Option<Sha256Digest>,
+    recorder_public_key: Option<ManifestKey>,
+    allow_unverified_recorder: bool,
+    recorder_manifest_url: Option<String>,
+    update_interval: Duration,
+    proxy: Option<String>,
+    no_proxy: Vec<String>,
+    proxy_username: Option<String>,
+    proxy_password: Option<String>,
+    segment_duration: Duration,
+    fps: u32,
+    resolution: Resolution,
+    container: Option<Container>,
+    display: DisplaySelection,
+    display_layout: DisplayLayout,
+    capture_source: CaptureSourceKind,
+    upload_attempts: u32,
+    retry_delay: Duration,
+    upload_workers: u32,
+    stop_timeout: Duration,
+    upload_partial: bool,
+    watchdog_grace: Duration,
+    upload_chunk_size: usize,
+    connect_timeout: Duration,
+    upload_timeout: Duration,
+    keepalive: Option<Duration>,
+    max_message_size: usize,
+}
+
+impl Default for RecordingConfigBuilder {
+    fn default() -> Self {
+        Self {
+            user_id: None,
+            api_url: None,
+            recorder_backend: RecorderBackend::External,
+            recorder_url: None,
+            recorder_mirrors: Vec::new(),
+            grpc_endpoint: None,
+            grpc_ca_cert: None,
+            grpc_server_name: None,
+            grpc_client_cert: None,
+            grpc_client_key: None,
+            insecure_tls: false,
+            api_pins: Vec::new(),
+            recorder_pins: Vec::new(),
+            recorder_sha256: None,
+            recorder_public_key: None,
+            allow_unverified_recorder: false,
+            recorder_manifest_url: None,
+            update_interval: Duration::from_secs(3600),
+            proxy: None,
+            no_proxy: Vec::new(),
+            proxy_username: None,
+            proxy_password: None,
+            segment_duration: Duration::from_secs(120),
+            fps: 24,
+            resolution: Resolution::HD,
+            container: None,
+            display: DisplaySelection::Primary,
+            display_layout: DisplayLayout::Composite,
+            capture_source: CaptureSourceKind::Display,
+            upload_attempts: 3,
+            retry_delay: Duration::from_secs(5),
+            upload_workers: 2,
+            stop_timeout: Duration::from_secs(10),
+            upload_partial: true,
+            watchdog_grace: Duration::from_secs(30),
+            upload_chunk_size: 1024 * 1024,
+            connect_timeout: Duration::from_secs(10),
+            upload_timeout: Duration::from_secs(600),
+            keepalive: Some(Duration::from_secs(30)),
+            max_message_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl RecordingConfigBuilder {
+    pub fn user_id(mut self, user_id: impl Into<String>) -> Self {
+        self.user_id = Some(user_id.into());
+        self
+    }
+
+    pub fn api_url(mut self, url: impl Into<String>) -> Self {
+        self.api_url = Some(url.into());
+        self
+    }
+
+    pub fn recorder_backend(mut self, backend: RecorderBackend) -> Self {
+        self.recorder_backend = backend;
+        self
+    }
+
+    pub fn recorder_url(mut self, url: impl Into<String>) -> Self {
+        self.recorder_url = Some(url.into());
+        self
+    }
+
+    /// URLs serving the same recorder executable, tried in order when
+    /// `recorder_url` fails. The recorder's digest and pins apply to all.
+    pub fn recorder_mirrors(mut self, urls: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.recorder_mirrors = urls.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// gRPC upload endpoint as a URI, e.g. `http://23.98.93.20:50057`.
+    pub fn grpc_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.grpc_endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Plaintext gRPC upload endpoint given as a socket address.
+    pub fn grpc_addr(self, addr: SocketAddr) -> Self {
+        self.grpc_endpoint(format!("http://{}", addr))
+    }
+
+    /// PEM CA bundle trusted for the gRPC server in addition to the system
+    /// roots.
+    pub fn grpc_ca_cert(mut self, path: impl Into<PathBuf>) -> Self {
+        self.grpc_ca_cert = Some(path.into());
+        self
+    }
+
+    /// Name to verify the gRPC server certificate against, for endpoints
+    /// given as an IP address.
+    pub fn grpc_server_name(mut self, name: impl Into<String>) -> Self {
+        self.grpc_server_name = Some(name.into());
+        self
+    }
+
+    /// PEM client certificate for mutual TLS; needs [`grpc_client_key`](Self::grpc_client_key).
+    pub fn grpc_client_cert(mut self, path: impl Into<PathBuf>) -> Self {
+        self.grpc_client_cert = Some(path.into());
+        self
+    }
+
+    /// PEM private key for [`grpc_client_cert`](Self::grpc_client_cert).
+    pub fn grpc_client_key(mut self, path: impl Into<PathBuf>) -> Self {
+        self.grpc_client_key = Some(path.into());
+        self
+    }
+
+    /// Accept any certificate from the API and recorder hosts. Only for
+    /// debugging; a warning is logged for every client built this way.
+    pub fn insecure_tls(mut self, insecure: bool) -> Self {
+        self.insecure_tls = insecure;
+        self
+    }
+
+    /// Public keys the API host's certificate chain must contain one of.
+    pub fn api_pins(mut self, pins: impl IntoIterator<Item = SpkiPin>) -> Self {
+        self.api_pins = pins.into_iter().collect();
+        self
+    }
+
+    /// Public keys the recorder host's certificate chain must contain one of.
+    pub fn recorder_pins(mut self, pins: impl IntoIterator<Item = SpkiPin>) -> Self {
+        self.recorder_pins = pins.into_iter().collect();
+        self
+    }
+
+    /// Only run a recorder with this SHA-256.
+    pub fn recorder_sha256(mut self, digest: Sha256Digest) -> Self {
+        self.recorder_sha256 = Some(digest);
+        self
+    }
+
+    /// Require the `<recorder_url>.sha256` manifest to carry a valid
+    /// signature by this key.
+    pub fn recorder_public_key(mut self, key: ManifestKey) -> Self {
+        self.recorder_public_key = Some(key);
+        self
+    }
+
+    /// Run a recorder that cannot be verified instead of refusing to. Only
+    /// for recorder hosts that publish no digest.
+    pub fn allow_unverified_recorder(mut self, allow: bool) -> Self {
+        self.allow_unverified_recorder = allow;
+        self
+    }
+
+    pub fn recorder_manifest_url(mut self, url: impl Into<String>) -> Self {
+        self.recorder_manifest_url = Some(url.into());
+        self
+    }
+
+    pub fn update_interval(mut self, interval: Duration) -> Self {
+        self.update_interval = interval;
+        self
+    }
+
+    /// `system` (the default) follows `HTTPS_PROXY`, `HTTP_PROXY`,
+    /// `ALL_PROXY` and `NO_PROXY`, `none` connects directly and an
+    /// `http://host:port` URL is used for every connection.
+    pub fn proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+
+    /// Hosts, domains and IP addresses reached without the proxy.
+    pub fn no_proxy(mut self, hosts: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.no_proxy = hosts.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Basic auth for the proxy; needs [`proxy_password`](Self::proxy_password).
+    pub fn proxy_username(mut self, username: impl Into<String>) -> Self {
+        self.proxy_username = Some(username.into());
+        self
+    }
+
+    pub fn proxy_password(mut self, password: impl Into<String>) -> Self {
+        self.proxy_password = Some(password.into());
+        self
+    }
+
+    pub fn segment_duration(mut self, duration: Duration) -> Self {
+        self.segment_duration = duration;
+        self
+    }
+
+    pub fn fps(mut self, fps: u32) -> Self {
+        self.fps = fps;
+        self
+    }
+
+    pub fn resolution(mut self, resolution: Resolution) -> Self {
+        self.resolution = resolution;
+        self
+    }
+
+    /// Defaults to the recorder backend's preferred container: WebM for
+    /// the external recorder, MP4 for the native one.
+    pub fn container(mut self, container: Container) -> Self {
+        self.container = Some(container);
+        self
+    }
+
+    /// Anything but the primary display needs the native recorder.
+    pub fn display(mut self, display: DisplaySelection) -> Self {
+        self.display = display;
+        self
+    }
+
+    pub fn display_layout(mut self, layout: DisplayLayout) -> Self {
+        self.display_layout = layout;
+        self
+    }
+
+    /// Anything but real displays needs the native recorder.
+    pub fn capture_source(mut self, source: CaptureSourceKind) -> Self {
+        self.capture_source = source;
+        self
+    }
+
+    /// Total number of upload attempts, including the first one.
+    pub fn upload_attempts(mut self, attempts: u32) -> Self {
+        self.upload_attempts = attempts;
+        self
+    }
+
+    /// Base delay before a failed recording, upload or API notification is
+    /// tried again; must not be zero.
+    pub fn retry_delay(mut self, delay: Duration) -> Self {
+        self.retry_delay = delay;
+        self
+    }
+
+    pub fn upload_workers(mut self, workers: u32) -> Self {
+        self.upload_workers = workers;
+        self
+    }
+
+    pub fn stop_timeout(mut self, timeout: Duration) -> Self {
+        self.stop_timeout = timeout;
+        self
+    }
+
+    pub fn upload_partial(mut self, upload: bool) -> Self {
+        self.upload_partial = upload;
+        self
+    }
+
+    pub fn watchdog_grace(mut self, grace: Duration) -> Self {
+        self.watchdog_grace = grace;
+        self
+    }
+
+    pub fn upload_chunk_size(mut self, bytes: usize) -> Self {
+        self.upload_chunk_size = bytes;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn upload_timeout(mut self, timeout: Duration) -> Self {
+        self.upload_timeout = timeout;
+        self
+    }
+
+    pub fn keepalive(mut self, interval: Option<Duration>) -> Self {
+        self.keepalive = interval;
+        self
+    }
+
+    pub fn max_message_size(mut self, bytes: usize) -> Self {
+        self.max_message_size = bytes;
+        self
+    }
+
+    pub fn build(self) -> Result<RecordingConfig, ConfigError> {
+        let user_id = self.user_id.ok_or(ConfigError::MissingField("user_id"))?;
+        if user_id.trim().is_empty() {
+            return Err(ConfigError::EmptyUserId);
+        }
+
+        let api_url = parse_http_url("api_url", self.api_url)?;
+        let recorder_url = if self.recorder_backend.capabilities().downloads_recorder
+            || self.recorder_url.is_some()
+        {
+            Some(parse_http_url("recorder_url", self.recorder_url)?)
+        } else {
+            None
+        };
+        let recorder_mirrors = self
+            .recorder_mirrors
+            .into_iter()
+            .map(|url| parse_http_url("recorder_mirrors", Some(url)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let grpc_endpoint = parse_grpc_endpoint(self.grpc_endpoint)?;
+        let recorder_manifest_url = self
+            .recorder_manifest_url
+            .map(|url| parse_http_url("recorder_manifest_url", Some(url)))
+            .transpose()?;
+        if self.insecure_tls {
+            if !self.api_pins.is_empty() {
+                return Err(ConfigError::InsecurePins("api_pins"));
+            }
+            if !self.recorder_pins.is_empty() {
+                return Err(ConfigError::InsecurePins("recorder_pins"));
+            }
+        }
+        if !self.api_pins.is_empty() && api_url.scheme() != "https" {
+            return Err(ConfigError::TlsRequiresHttps("api_pins"));
+        }
+        if !self.recorder_pins.is_empty()
+            && recorder_url
+                .iter()
+                .chain(&recorder_mirrors)
+                .any(|url| url.scheme() != "https")
+        {
+            return Err(ConfigError::TlsRequiresHttps("recorder_pins"));
+        }
+        let client_identity = match (self.grpc_client_cert, self.grpc_client_key) {
+            (Some(cert), Some(key)) => Some((cert, key)),
+            (None, None) => None,
+            _ => return Err(ConfigError::IncompleteClientIdentity),
+        };
+        let grpc_tls = if grpc_endpoint.scheme_str() == Some("https") {
+            Some(GrpcTls {
+                ca_cert: self.grpc_ca_cert,
+                server_name: self.grpc_server_name,
+                client_identity,
+            })
+        } else if self.grpc_ca_cert.is_some() {
+            return Err(ConfigError::TlsRequiresHttps("grpc_ca_cert"));
+        } else if self.grpc_server_name.is_some() {
+            return Err(ConfigError::TlsRequiresHttps("grpc_server_name"));
+        } else if client_identity.is_some() {
+            return Err(ConfigError::TlsRequiresHttps("a client certificate"));
+        } else {
+            None
+        };
+
+        if self.update_interval.is_zero() {
+            return Err(ConfigError::Zero("update_interval"));
+        }
+        let proxy = ProxyConfig {
+            mode: parse_proxy_mode(self.proxy)?,
+            no_proxy: self.no_proxy,
+            auth: match (self.proxy_username, self.proxy_password) {
+                (Some(username), Some(password)) => Some((username, password)),
+                (None, None) => None,
+                _ => return Err(ConfigError::IncompleteProxyAuth),
+            },
+        };
+        if self.segment_duration.is_zero() {
+            return Err(ConfigError::Zero("segment_duration"));
+        }
+        let supported = self.recorder_backend.capabilities().containers;
+        let container = self.container.unwrap_or(supported[0]);
+        if !supported.contains(&container) {
+            return Err(ConfigError::UnsupportedContainer {
+                backend: self.recorder_backend,
+                container,
+            });
+        }
+        if self.display != DisplaySelection::Primary
+            && !self.recorder_backend.capabilities().selects_display
+        {
+            return Err(ConfigError::UnsupportedDisplay(self.recorder_backend));
+        }
+        if self.capture_source != CaptureSourceKind::Display
+            && !self.recorder_backend.capabilities().synthetic_sources
+        {
+            return Err(ConfigError::UnsupportedCaptureSource(self.recorder_backend));
+        }
+        let fps = NonZeroU32::new(self.fps).ok_or(ConfigError::Zero("fps"))?;
+        let resolution = Resolution::new(self.resolution.width, self.resolution.height)?;
+        let upload_attempts =
+            NonZeroU32::new(self.upload_attempts).ok_or(ConfigError::Zero("upload_attempts"))?;
+        // A failed upload would otherwise be due again at once
+        if self.retry_delay.is_zero() {
+            return Err(ConfigError::Zero("retry_delay"));
+        }
+        let upload_workers =
+            NonZeroU32::new(self.upload_workers).ok_or(ConfigError::Zero("upload_workers"))?;
+        if self.upload_chunk_size == 0 {
+            return Err(ConfigError::Zero("upload_chunk_size"));
+        }
+        if self.connect_timeout.is_zero() {
+            return Err(ConfigError::Zero("connect_timeout"));
+        }
+        if self.upload_timeout.is_zero() {
+            return Err(ConfigError::Zero("upload_timeout"));
+        }
+        // Leave room for the chunk's offset, checksum and framing
+        let message = self.upload_chunk_size.checked_add(MESSAGE_OVERHEAD);
+        if message.is_none_or(|message| message > self.max_message_size) {
+            return Err(ConfigError::ChunkTooLarge {
+                chunk: self.upload_chunk_size,
+                max: self.max_message_size,
+            });
+        }
+
+        Ok(RecordingConfig {
+            user_id,
+            api_url,
+            recorder_backend: self.recorder_backend,
+            recorder_url,
+            recorder_mirrors,
+            grpc_endpoint,
+            grpc_tls,
+            insecure_tls: self.insecure_tls,
+            api_pins: self.api_pins,
+            recorder_pins: self.recorder_pins,
+            recorder_sha256: self.recorder_sha256,
+            recorder_public_key: self.recorder_public_key,
+            allow_unverified_recorder: self.allow_unverified_recorder,
+            recorder_manifest_url,
+            update_interval: self.update_interval,
+            proxy,
+            segment_duration: self.segment_duration,
+            fps,
+            resolution,
+            container,
+            display: self.display,
+            display_layout: self.display_layout,
+            capture_source: self.capture_source,
+            upload_attempts,
+            retry_delay: self.retry_delay,
+            upload_workers,
+            stop_timeout: self.stop_timeout,
+            upload_partial: self.upload_partial,
+            watchdog_grace: self.watchdog_grace,
+            upload_chunk_size: self.upload_chunk_size,
+            connect_timeout: self.connect_timeout,
+            upload_timeout: self.upload_timeout,
+            keepalive: self.keepalive.filter(|interval| !interval.is_zero()),
+            max_message_size: self.max_message_size,
+        })
+    }
+}
+
+fn parse_http_url(field: &'static str, value: Option<String>) -> Result<Url, ConfigError> {
+    let value = value.ok_or(ConfigError::MissingField(field))?;
+    let url = Url::parse(value.trim()).map_err(|source| ConfigError::InvalidUrl {
+        field,
+        value: value.clone(),
+        source,
+    })?;
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        other => Err(ConfigError::UnsupportedScheme {
+            field,
+            scheme: other.to_string(),
+        }),
+    }
+}
+
+fn parse_proxy_mode(value: Option<String>) -> Result<ProxyMode, ConfigError> {
+    let Some(value) = value else {
+        return Ok(ProxyMode::System);
+    };
+    match value.trim().to_ascii_lowercase().as_str() {
+        "" | "system" => return Ok(ProxyMode::System),
+        "none" | "direct" => return Ok(ProxyMode::None),
+        _ => {}
+    }
+    // HTTPS and SOCKS proxies are not supported by the gRPC tunnel
+    match Url::parse(value.trim()) {
+        Ok(url) if url.scheme() == "http" && url.host_str().is_some() => Ok(ProxyMode::Url(url)),
+        _ => Err(ConfigError::InvalidProxy(value)),
+    }
+}
+
+fn parse_grpc_endpoint(value: Option<String>) -> Result<Uri, ConfigError> {
+    let value = value.ok_or(ConfigError::MissingField("grpc_endpoint"))?;
+    let invalid = |reason: &str| ConfigError::InvalidEndpoint {
+        value: value.clone(),
+        reason: reason.to_string(),
+    };
+
+    let uri: Uri = value
+        .trim()
+        .parse()
+        .map_err(|_| invalid("not a valid URI"))?;
+    match uri.scheme_str() {
+        Some("http") | Some("https") => {}
+        Some(other) => {
+            return Err(ConfigError::UnsupportedScheme {
+                field: "grpc_endpoint",
+                scheme: other.to_string(),
+            })
+        }
+        None => return Err(invalid("missing scheme, expected http:// or https://")),
+    }
+    if uri.host().is_none_or(str::is_empty) {
+        return Err(invalid("missing host"));
+    }
+    if uri.port_u16().is_none() {
+        return Err(invalid("missing port"));
+    }
+    Ok(uri)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The smallest configuration that builds.
+    fn valid() -> RecordingConfigBuilder {
+        RecordingConfig::builder()
+            .user_id("tester")
+            .api_url("https://api.example.com/events")
+            .recorder_url("https://downloads.example.com/screen_record.exe")
+            .grpc_endpoint("http://127.0.0.1:50057")
+    }
+
+    fn pin() -> SpkiPin {
+        "sha256/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn insecure_tls_cannot_be_combined_with_pins() {
+        let api = valid().insecure_tls(true).api_pins([pin()]).build();
+        assert!(matches!(api, Err(ConfigError::InsecurePins("api_pins"))));
+
+        let recorder = valid().insecure_tls(true).recorder_pins([pin()]).build();
+        assert!(matches!(
+            recorder,
+            Err(ConfigError::InsecurePins("recorder_pins"))
+        ));
+
+        let config = valid().insecure_tls(true).build().unwrap();
+        assert!(config.api_tls().insecure);
+        valid().api_pins([pin()]).build().unwrap();
+    }
+
+    #[test]
+    fn recorder_url_is_only_required_for_the_external_backend() {
+        let external = RecordingConfig::builder()
+            .user_id("tester")
+            .api_url("https://api.example.com/events")
+            .grpc_endpoint("http://127.0.0.1:50057");
+        let native = external.clone().recorder_backend(RecorderBackend::Native);
+
+        assert!(matches!(
+            external.build(),
+            Err(ConfigError::MissingField("recorder_url"))
+        ));
+        let config = native.build().unwrap();
+        assert_eq!(config.recorder_url(), None);
+        assert_eq!(config.recorder_urls().count(), 0);
+    }
+
+    #[test]
+    fn valid_build_fills_in_the_defaults() {
+        let config = valid().build().unwrap();
+        assert_eq!(config.user_id(), "tester");
+        assert_eq!(config.recorder_backend(), RecorderBackend::External);
+        assert_eq!(config.container(), Container::Webm);
+        assert_eq!(config.fps().get(), 24);
+        assert_eq!(config.segment_duration(), Duration::from_secs(120));
+        assert_eq!(
+            (config.grpc_host(), config.grpc_port()),
+            ("127.0.0.1", 50057)
+        );
+        assert!(config.grpc_tls().is_none());
+        assert_eq!(config.proxy().mode, ProxyMode::System);
+
+        let native = valid()
+            .recorder_backend(RecorderBackend::Native)
+            .build()
+            .unwrap();
+        assert_eq!(native.container(), Container::Mp4);
+    }
+
+    #[test]
+    fn user_id_is_required_and_not_blank() {
+        let missing = RecordingConfig::builder()
+            .api_url("https://api.example.com/events")
+            .recorder_url("https://downloads.example.com/screen_record.exe")
+            .grpc_endpoint("http://127.0.0.1:50057")
+            .build();
+        assert!(matches!(missing, Err(ConfigError::MissingField("user_id"))));
+        assert!(matches!(
+            valid().user_id("  ").build(),
+            Err(ConfigError::EmptyUserId)
+        ));
+    }
+
+    #[test]
+    fn urls_must_parse_as_http() {
+        assert!(matches!(
+            valid().api_url("").build(),
+            Err(ConfigError::InvalidUrl {
+                field: "api_url",
+                ..
+            })
+        ));
+        assert!(matches!(
+            valid().api_url("ftp://api.example.com").build(),
+            Err(ConfigError::UnsupportedScheme {
+                field: "api_url",
+                ..
+            })
+        ));
+        assert!(matches!(
+            valid().recorder_url("downloads.example.com").build(),
+            Err(ConfigError::InvalidUrl {
+                field: "recorder_url",
+                ..
+            })
+        ));
+        assert!(matches!(
+            valid()
+                .recorder_mirrors(["file:///tmp/screen_record.exe"])
+                .build(),
+            Err(ConfigError::UnsupportedScheme {
+                field: "recorder_mirrors",
+                ..
+            })
+        ));
+        assert!(matches!(
+            valid().recorder_manifest_url("").build(),
+            Err(ConfigError::InvalidUrl {
+                field: "recorder_manifest_url",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn grpc_endpoint_needs_a_scheme_host_and_port() {
+        let missing = RecordingConfig::builder()
+            .user_id("tester")
+            .api_url("https://api.example.com/events")
+            .recorder_url("https://downloads.example.com/screen_record.exe")
+            .build();
+        assert!(matches!(
+            missing,
+            Err(ConfigError::MissingField("grpc_endpoint"))
+        ));
+
+        for endpoint in ["", "127.0.0.1:50057", "http://127.0.0.1"] {
+            assert!(
+                matches!(
+                    valid().grpc_endpoint(endpoint).build(),
+                    Err(ConfigError::InvalidEndpoint { .. })
+                ),
+                "{:?}",
+                endpoint
+            );
+        }
+        assert!(matches!(
+            valid().grpc_endpoint("ws://127.0.0.1:50057").build(),
+            Err(ConfigError::UnsupportedScheme {
+                field: "grpc_endpoint",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn zero_values_are_rejected() {
+        let cases = [
+            ("update_interval", valid().update_interval(Duration::ZERO)),
+            ("segment_duration", valid().segment_duration(Duration::ZERO)),
+            ("fps", valid().fps(0)),
+            ("upload_attempts", valid().upload_attempts(0)),
+            ("retry_delay", valid().retry_delay(Duration::ZERO)),
+            ("upload_workers", valid().upload_workers(0)),
+            ("upload_chunk_size", valid().upload_chunk_size(0)),
+            ("connect_timeout", valid().connect_timeout(Duration::ZERO)),
+            ("upload_timeout", valid().upload_timeout(Duration::ZERO)),
+        ];
+        for (field, builder) in cases {
+            match builder.build() {
+                Err(ConfigError::Zero(zero)) => assert_eq!(zero, field),
+                other => panic!("{}: {:?}", field, other.map(|_| ())),
+            }
+        }
+        // A zero keepalive disables it instead
+        let config = valid().keepalive(Some(Duration::ZERO)).build().unwrap();
+        assert_eq!(config.keepalive(), None);
+    }
+
+    #[test]
+    fn backend_must_support_the_container_display_and_source() {
+        assert!(matches!(
+            valid()
+                .recorder_backend(RecorderBackend::Native)
+                .container(Container::Webm)
+                .build(),
+            Err(ConfigError::UnsupportedContainer {
+                backend: RecorderBackend::Native,
+                container: Container::Webm,
+            })
+        ));
+        assert!(matches!(
+            valid().display(DisplaySelection::All).build(),
+            Err(ConfigError::UnsupportedDisplay(RecorderBackend::External))
+        ));
+        assert!(matches!(
+            valid()
+                .capture_source(CaptureSourceKind::TestPattern)
+                .build(),
+            Err(ConfigError::UnsupportedCaptureSource(
+                RecorderBackend::External
+            ))
+        ));
+
+        let native = valid()
+            .recorder_backend(RecorderBackend::Native)
+            .container(Container::Mkv)
+            .display(DisplaySelection::Index(1))
+            .capture_source(CaptureSourceKind::TestPattern)
+            .build()
+            .unwrap();
+        assert_eq!(native.container(), Container::Mkv);
+    }
+
+    #[test]
+    fn tls_settings_require_https() {
+        assert!(matches!(
+            valid()
+                .api_url("http://api.example.com/events")
+                .api_pins([pin()])
+                .build(),
+            Err(ConfigError::TlsRequiresHttps("api_pins"))
+        ));
+        assert!(matches!(
+            valid()
+                .recorder_mirrors(["http://mirror.example.com/screen_record.exe"])
+                .recorder_pins([pin()])
+                .build(),
+            Err(ConfigError::TlsRequiresHttps("recorder_pins"))
+        ));
+        assert!(matches!(
+            valid().grpc_ca_cert("ca.pem").build(),
+            Err(ConfigError::TlsRequiresHttps("grpc_ca_cert"))
+        ));
+        assert!(matches!(
+            valid().grpc_server_name("upload.example.com").build(),
+            Err(ConfigError::TlsRequiresHttps("grpc_server_name"))
+        ));
+        assert!(matches!(
+            valid()
+                .grpc_client_cert("client.pem")
+                .grpc_client_key("client.key")
+                .build(),
+            Err(ConfigError::TlsRequiresHttps("a client certificate"))
+        ));
+
+        let config = valid()
+            .grpc_endpoint("https://127.0.0.1:50057")
+            .grpc_ca_cert("ca.pem")
+            .grpc_server_name("upload.example.com")
+            .build()
+            .unwrap();
+        let tls = config.grpc_tls().unwrap();
+        assert_eq!(tls.server_name.as_deref(), Some("upload.example.com"));
+    }
+
+    #[test]
+    fn paired_settings_must_be_set_together() {
+        let https = valid().grpc_endpoint("https://127.0.0.1:50057");
+        assert!(matches!(
+            https.clone().grpc_client_cert("client.pem").build(),
+            Err(ConfigError::IncompleteClientIdentity)
+        ));
+        assert!(matches!(
+            https.grpc_client_key("client.key").build(),
+            Err(ConfigError::IncompleteClientIdentity)
+        ));
+        assert!(matches!(
+            valid().proxy_username("agent").build(),
+            Err(ConfigError::IncompleteProxyAuth)
+        ));
+        assert!(matches!(
+            valid().proxy_password("secret").build(),
+            Err(ConfigError::IncompleteProxyAuth)
+        ));
+    }
+
+    #[test]
+    fn proxy_must_be_system_none_or_an_http_url() {
+        for proxy in [
+            "socks5://proxy.local:1080",
+            "https://proxy.local:3128",
+            "proxy.local",
+        ] {
+            assert!(
+                matches!(
+                    valid().proxy(proxy).build(),
+                    Err(ConfigError::InvalidProxy(_))
+                ),
+                "{:?}",
+                proxy
+            );
+        }
+        assert_eq!(
+            valid().proxy("none").build().unwrap().proxy().mode,
+            ProxyMode::None
+        );
+        assert!(matches!(
+            valid()
+                .proxy("http://proxy.local:3128")
+                .build()
+                .unwrap()
+                .proxy()
+                .mode,
+            ProxyMode::Url(_)
+        ));
+    }
+
+    #[test]
+    fn upload_chunks_must_fit_in_a_message() {
+        let chunk = 1024 * 1024;
+        assert!(matches!(
+            valid()
+                .upload_chunk_size(chunk)
+                .max_message_size(chunk)
+                .build(),
+            Err(ConfigError::ChunkTooLarge { .. })
+        ));
+        valid()
+            .upload_chunk_size(chunk)
+            .max_message_size(chunk + MESSAGE_OVERHEAD)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            valid()
+                .upload_chunk_size(usize::MAX)
+                .max_message_size(usize::MAX)
+                .build(),
+            Err(ConfigError::ChunkTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn resolution_sides_must_be_non_zero() {
+        assert!(matches!(
+            Resolution::new(0, 720),
+            Err(ConfigError::InvalidResolution(_))
+        ));
+        assert!(matches!(
+            Resolution::new(1280, 0),
+            Err(ConfigError::InvalidResolution(_))
+        ));
+    }
+}
@@ -0,0 +1,226 @@
+use std::fmt;
+use std::fs;
+
+use crate::config::{RecorderBackend, RecordingConfig};
+use crate::queue::{self, UploadState};
+use crate::recorder::NativeRecorder;
+use crate::run::{AppDirs, MIN_RECORDER_SIZE};
+use crate::updater;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            CheckStatus::Ok => "OK",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+/// Outcome of the environment checks run by [`run_doctor`].
+#[derive(Debug, Clone, Default)]
+pub struct DoctorReport {
+    pub checks: Vec<Check>,
+}
+
+impl DoctorReport {
+    fn push(&mut self, name: &'static str, status: CheckStatus, detail: impl Into<String>) {
+        self.checks.push(Check {
+            name,
+            status,
+            detail: detail.into(),
+        });
+    }
+
+    /// `true` when no check failed; warnings are allowed.
+    pub fn is_healthy(&self) -> bool {
+        self.checks.iter().all(|c| c.status != CheckStatus::Fail)
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            writeln!(f, "[{:4}] {}: {}", check.status, check.name, check.detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Check the app directory, temp directory, recorder executable, leftover
+/// recordings and (when given) the effective configuration.
+pub fn run_doctor(config: Result<&RecordingConfig, String>) -> DoctorReport {
+    let mut report = DoctorReport::default();
+    let (backend, capture_source) = config
+        .as_ref()
+        .map(|config| (config.recorder_backend(), config.capture_source().clone()))
+        .unwrap_or_default();
+
+    match config {
+        Ok(config) => {
+            report.push(
+                "config",
+                CheckStatus::Ok,
+                format!(
+                    "user {} → {} ({}s segments)",
+                    config.user_id(),
+                    config.grpc_endpoint(),
+                    config.segment_duration().as_secs()
+                ),
+            );
+            if config.api_tls().insecure {
+                report.push(
+                    "tls",
+                    CheckStatus::Warn,
+                    "certificate verification is disabled (insecure_tls = true)",
+                );
+            }
+            if config.allow_unverified_recorder() {
+                report.push(
+                    "integrity",
+                    CheckStatus::Warn,
+                    "a recorder without a digest is run unverified (allow_unverified_recorder = true)",
+                );
+            }
+            let api_url = config.api_url();
+            let api_host = api_url.host_str().unwrap_or_default();
+            if let Some(mut proxy) = config.proxy().proxy_for(api_url.scheme(), api_host) {
+                let _ = proxy.set_password(None);
+                report.push(
+                    "proxy",
+                    CheckStatus::Ok,
+                    format!("API requests go through {}", proxy),
+                );
+            }
+        }
+        Err(e) => report.push("config", CheckStatus::Fail, e),
+    }
+
+    let dirs = match AppDirs::locate() {
+        Ok(dirs) => dirs,
+        Err(e) => {
+            report.push("app directory", CheckStatus::Fail, e.to_string());
+            return report;
+        }
+    };
+
+    report.push(
+        "app directory",
+        CheckStatus::Ok,
+        format!("{} (source: {})", dirs.app_dir.display(), dirs.info.source),
+    );
+    for (source, path, exists) in &dirs.info.checked_paths {
+        let status = if *exists { "exists" } else { "not found" };
+        report.push(
+            "checked path",
+            CheckStatus::Ok,
+            format!("[{}] {}: {}", status, source, path.display()),
+        );
+    }
+
+    let probe = dirs.tmp_dir.join(".doctor_probe");
+    let temp_result = fs::create_dir_all(&dirs.tmp_dir)
+        .and_then(|_| fs::write(&probe, b"ok"))
+        .and_then(|_| fs::remove_file(&probe));
+    match temp_result {
+        Ok(()) => report.push(
+            "temp directory",
+            CheckStatus::Ok,
+            format!("{} is writable", dirs.tmp_dir.display()),
+        ),
+        Err(e) => report.push(
+            "temp directory",
+            CheckStatus::Fail,
+            format!("{}: {}", dirs.tmp_dir.display(), e),
+        ),
+    }
+
+    if backend == RecorderBackend::Native {
+        match NativeRecorder::probe(&capture_source) {
+            Ok(detail) => report.push("recorder", CheckStatus::Ok, detail),
+            Err(e) => report.push("recorder", CheckStatus::Fail, e),
+        }
+    } else {
+        check_recorder_exe(&mut report, &dirs);
+    }
+
+    match queue::read_entries(&dirs.app_dir) {
+        Ok(entries) if entries.is_empty() => {
+            report.push("pending uploads", CheckStatus::Ok, "none")
+        }
+        Ok(entries) => {
+            let failed = entries
+                .iter()
+                .filter(|e| e.state == UploadState::Failed)
+                .count();
+            let detail = format!(
+                "{} segment(s) queued, {} failed, {} bytes",
+                entries.len(),
+                failed,
+                entries.iter().map(|e| e.size).sum::<u64>()
+            );
+            let status = if failed > 0 {
+                CheckStatus::Warn
+            } else {
+                CheckStatus::Ok
+            };
+            report.push("pending uploads", status, detail)
+        }
+        Err(e) => report.push("pending uploads", CheckStatus::Fail, e.to_string()),
+    }
+
+    report
+}
+
+/// Report on the downloaded recorder executable, preferring the version the
+/// updater activated.
+fn check_recorder_exe(report: &mut DoctorReport, dirs: &AppDirs) {
+    let version = updater::active_version(&dirs.bin_dir);
+    let exe = match &version {
+        Some(version) => updater::recorder_path(&dirs.bin_dir, version),
+        None => dirs.recorder_exe(),
+    };
+    match fs::metadata(&exe) {
+        Ok(metadata) if metadata.len() >= MIN_RECORDER_SIZE => report.push(
+            "recorder",
+            CheckStatus::Ok,
+            match &version {
+                Some(version) => format!(
+                    "{} {} ({} MB)",
+                    version,
+                    exe.display(),
+                    metadata.len() / 1_000_000
+                ),
+                None => format!("{} ({} MB)", exe.display(), metadata.len() / 1_000_000),
+            },
+        ),
+        Ok(metadata) => report.push(
+            "recorder",
+            CheckStatus::Warn,
+            format!(
+                "{} is only {} bytes and will be re-downloaded",
+                exe.display(),
+                metadata.len()
+            ),
+        ),
+        Err(_) => report.push(
+            "recorder",
+            CheckStatus::Warn,
+            format!("{} not present yet, it will be downloaded", exe.display()),
+        ),
+    }
+}
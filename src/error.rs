@@ -0,0 +1,157 @@
+use std::io;
+use std::path::PathBuf;
+
+use crate::agent_config::AgentConfigError;
+use crate::config::ConfigError;
+use crate::integrity::IntegrityError;
+use crate::modules::api::grpc_upload::UploadError;
+use crate::process::ExitKind;
+
+pub type Result<T, E = ScreenRecordError> = std::result::Result<T, E>;
+
+/// Everything that can go wrong in a recording cycle, with the diagnostic
+/// data as fields so callers can branch on the failure and render their own
+/// messages. [`ScreenRecordError::hint`] offers a short troubleshooting tip.
+#[derive(Debug, thiserror::Error)]
+pub enum ScreenRecordError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+
+    #[error(transparent)]
+    AgentConfig(#[from] AgentConfigError),
+
+    #[error("could not determine the app directory: {0}")]
+    AppDir(#[source] io::Error),
+
+    #[error("temp directory {} is not usable: {source}", path.display())]
+    TempDir { path: PathBuf, source: io::Error },
+
+    #[error("failed to download the recorder from {url}: {}", failures.join("; "))]
+    RecorderDownload { url: String, failures: Vec<String> },
+
+    #[error("recorder executable {} is not accessible: {source}", path.display())]
+    RecorderMissing {
+        path: PathBuf,
+        /// `(source, path, exists)` for every location the app directory lookup tried.
+        checked_paths: Vec<(String, PathBuf, bool)>,
+        source: io::Error,
+    },
+
+    #[error("recorder from {url} failed verification: {reason}")]
+    RecorderIntegrity {
+        url: String,
+        path: PathBuf,
+        #[source]
+        reason: IntegrityError,
+    },
+
+    #[error("recorder update from {url} failed: {reason}")]
+    RecorderUpdate { url: String, reason: String },
+
+    #[error("recorder {version} requires agent {required} or newer, this is {current}")]
+    AgentTooOld {
+        version: String,
+        required: String,
+        current: String,
+    },
+
+    #[error("no previous recorder version to roll back to")]
+    NoPreviousRecorder,
+
+    #[error("failed to start recorder {}: {source}", path.display())]
+    RecorderSpawn { path: PathBuf, source: io::Error },
+
+    #[error("recorder {exit} without producing a recording")]
+    RecorderExit {
+        exit: ExitKind,
+        /// The last lines the recorder wrote to stderr.
+        stderr: String,
+    },
+
+    #[error("native screen capture failed: {reason}")]
+    NativeCapture { reason: String },
+
+    #[error("recording was cancelled")]
+    Cancelled {
+        /// Partial recording left in the temp directory for a later upload.
+        kept: Option<PathBuf>,
+    },
+
+    #[error("no recording was created in {}", dir.display())]
+    NoOutputFile { dir: PathBuf },
+
+    #[error("recording {} is empty", path.display())]
+    EmptyOutput { path: PathBuf },
+
+    #[error("upload of {} failed after {attempts} attempt(s): {last_error}", path.display())]
+    Upload {
+        path: PathBuf,
+        attempts: u32,
+        last_error: String,
+    },
+
+    #[error("failed to notify the API: {message}")]
+    ApiNotify {
+        status: Option<u16>,
+        message: String,
+    },
+
+    #[error("failed to set up the upload channel: {0}")]
+    UploadChannel(#[source] UploadError),
+
+    #[error("failed to build HTTP client: {0}")]
+    HttpClient(#[source] reqwest::Error),
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl ScreenRecordError {
+    /// Troubleshooting tip suitable for showing to an operator.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            ScreenRecordError::RecorderDownload { .. } => Some(
+                "check that the recorder URL is reachable from this network and that \
+                 firewall/proxy settings allow the connection, or add recorder_mirrors",
+            ),
+            ScreenRecordError::RecorderMissing { .. } => Some(
+                "ensure the bin directory is writable and that antivirus software is not \
+                 removing the download",
+            ),
+            ScreenRecordError::RecorderIntegrity { .. } => Some(
+                "the recorder was not started; check recorder_sha256 and recorder_public_key \
+                 against the published build and that nothing on the network rewrites downloads",
+            ),
+            ScreenRecordError::RecorderUpdate { .. } => Some(
+                "check that recorder_manifest_url is reachable and serves a build for this \
+                 platform; the active recorder keeps being used",
+            ),
+            ScreenRecordError::AgentTooOld { .. } => {
+                Some("update the agent; the active recorder keeps being used")
+            }
+            ScreenRecordError::RecorderSpawn { .. } => Some(
+                "the executable may be corrupted, blocked by Windows (Properties > Unblock), \
+                 missing DLLs or blocked by antivirus software",
+            ),
+            ScreenRecordError::RecorderExit {
+                exit: ExitKind::TimedOut,
+                ..
+            } => Some(
+                "the recorder did not finish in time; raise watchdog_grace_secs if it needs \
+                 longer to finalize the file",
+            ),
+            ScreenRecordError::NativeCapture { .. } => Some(
+                "the native recorder needs an unlocked desktop session and ffmpeg next to the \
+                 agent or on PATH; run `doctor` to check both",
+            ),
+            ScreenRecordError::UploadChannel(_) => Some(
+                "check that grpc_ca_cert, grpc_client_cert and grpc_client_key point to \
+                 readable PEM files",
+            ),
+            ScreenRecordError::Upload { .. } => {
+                Some("the recording was kept in the temp directory; retry with `queue retry`")
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+
+/// Capacity of the event channel; slow subscribers see `RecvError::Lagged`
+/// instead of blocking the recording.
+pub const EVENT_CAPACITY: usize = 256;
+
+/// Progress of a recording cycle, for UIs embedding the library.
+///
+/// `segment` is the recording's file name without extension, see
+/// [`crate::run::segment_id`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordingEvent {
+    /// Recorder executable download progress. `total` is unknown when the
+    /// server sends no `Content-Length`.
+    RecorderDownloading { bytes: u64, total: Option<u64> },
+    /// A recorder download attempt failed and `url` is asked again after
+    /// `delay`.
+    RecorderDownloadRetry {
+        url: String,
+        attempt: u32,
+        delay: Duration,
+        error: String,
+    },
+    /// A recorder version from the manifest became active.
+    RecorderUpdated {
+        version: String,
+        previous: Option<String>,
+    },
+    RecordingStarted {
+        segment: String,
+        path: PathBuf,
+        duration: Duration,
+    },
+    /// One line printed by the recorder on stdout or stderr.
+    RecorderOutput { segment: String, line: String },
+    RecordingFinished {
+        segment: String,
+        path: PathBuf,
+        size: u64,
+    },
+    UploadProgress {
+        segment: String,
+        sent: u64,
+        total: u64,
+    },
+    UploadRetry {
+        segment: String,
+        attempt: u32,
+        delay: Duration,
+        error: String,
+    },
+    /// The API was told about the upload; `error` is set when that failed.
+    ApiNotified {
+        segment: String,
+        error: Option<String>,
+    },
+    /// The local copy of an uploaded recording was deleted (or not).
+    Cleanup {
+        segment: String,
+        path: PathBuf,
+        removed: bool,
+    },
+}
+
+/// Cloneable sending side of the event channel. Sending never fails: events
+/// are dropped when nobody is subscribed.
+#[derive(Debug, Clone)]
+pub struct EventSender(broadcast::Sender<RecordingEvent>);
+
+impl EventSender {
+    pub fn new() -> Self {
+        Self(broadcast::channel(EVENT_CAPACITY).0)
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<RecordingEvent> {
+        self.0.subscribe()
+    }
+
+    pub fn emit(&self, event: RecordingEvent) {
+        let _ = self.0.send(event);
+    }
+}
+
+impl Default for EventSender {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,260 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ed25519_dalek::{Signature, VerifyingKey};
+use log::warn;
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+
+/// Why a recorder executable could not be trusted.
+#[derive(Debug, thiserror::Error)]
+pub enum IntegrityError {
+    #[error("sha256 is {actual}, expected {expected}")]
+    HashMismatch {
+        expected: Sha256Digest,
+        actual: Sha256Digest,
+    },
+
+    #[error("could not fetch the recorder manifest {url}: {reason}")]
+    Manifest { url: String, reason: String },
+
+    #[error("recorder manifest {url} does not contain a sha256 digest")]
+    MalformedManifest { url: String },
+
+    #[error("signature of {url} does not match the configured public key")]
+    BadSignature { url: String },
+
+    #[error(
+        "no recorder_sha256 or manifest to verify it against; set \
+         allow_unverified_recorder to run it anyway"
+    )]
+    Unverified,
+
+    #[error("failed to hash the recorder: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// A SHA-256 digest, written as 64 hex digits.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Sha256Digest([u8; 32]);
+
+impl Sha256Digest {
+    /// Hash a file without reading it into memory at once.
+    pub fn of_file(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            match file.read(&mut buf)? {
+                0 => break,
+                n => hasher.update(&buf[..n]),
+            }
+        }
+        Ok(Self(hasher.finalize().into()))
+    }
+}
+
+impl FromStr for Sha256Digest {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s.trim()).map_err(|_| ())?;
+        Ok(Self(bytes.try_into().map_err(|_| ())?))
+    }
+}
+
+impl fmt::Display for Sha256Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0))
+    }
+}
+
+impl<'de> Deserialize<'de> for Sha256Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        hex.parse()
+            .map_err(|_| serde::de::Error::custom(format!("invalid sha256 digest `{}`", hex)))
+    }
+}
+
+impl fmt::Debug for Sha256Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Ed25519 key the recorder manifest is signed with, written as the base64
+/// of its 32 raw bytes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ManifestKey(VerifyingKey);
+
+impl ManifestKey {
+    /// Check a base64 Ed25519 `signature` over `message`.
+    pub fn verify(&self, message: &[u8], signature: &str) -> bool {
+        let Ok(bytes) = BASE64.decode(signature.trim()) else {
+            return false;
+        };
+        let Ok(signature) = Signature::from_slice(&bytes) else {
+            return false;
+        };
+        self.0.verify_strict(message, &signature).is_ok()
+    }
+}
+
+impl FromStr for ManifestKey {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes: [u8; 32] = BASE64
+            .decode(s.trim())
+            .map_err(|_| ())?
+            .try_into()
+            .map_err(|_| ())?;
+        VerifyingKey::from_bytes(&bytes).map(Self).map_err(|_| ())
+    }
+}
+
+impl fmt::Display for ManifestKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&BASE64.encode(self.0.as_bytes()))
+    }
+}
+
+impl fmt::Debug for ManifestKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Digest from a `<recorder_url>.sha256` manifest, in `sha256sum` format
+/// (`<hex>  <file name>`) or just the hex digest.
+pub fn parse_manifest(text: &str) -> Option<Sha256Digest> {
+    text.split_whitespace().next()?.parse().ok()
+}
+
+/// Fail unless `path` hashes to `expected`.
+pub fn verify_file(path: &Path, expected: Sha256Digest) -> Result<(), IntegrityError> {
+    let actual = Sha256Digest::of_file(path)?;
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(IntegrityError::HashMismatch { expected, actual })
+    }
+}
+
+/// Where the digest an installed executable was verified against is kept.
+fn digest_path(exe: &Path) -> PathBuf {
+    let mut name = exe.as_os_str().to_owned();
+    name.push(".sha256");
+    PathBuf::from(name)
+}
+
+/// Digest `exe` was verified against when it was installed.
+pub fn recorded_digest(exe: &Path) -> Option<Sha256Digest> {
+    parse_manifest(&fs::read_to_string(digest_path(exe)).ok()?)
+}
+
+/// Remember that `exe` was verified against `digest`, in `sha256sum` format.
+pub fn record_digest(exe: &Path, digest: Sha256Digest) {
+    let name = exe
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let path = digest_path(exe);
+    if let Err(e) = fs::write(&path, format!("{}  {}\n", digest, name)) {
+        warn!(path:% = path.display(), error:% = e; "failed to record the recorder digest");
+    }
+}
+
+pub fn forget_digest(exe: &Path) {
+    let _ = fs::remove_file(digest_path(exe));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "screen_record_integrity_{}_{}",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn verify_file_accepts_the_matching_digest() {
+        let path = temp_file("match", b"");
+        verify_file(&path, EMPTY_SHA256.parse().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn verify_file_reports_a_digest_mismatch() {
+        let path = temp_file("mismatch", b"tampered");
+        let expected: Sha256Digest = EMPTY_SHA256.parse().unwrap();
+        match verify_file(&path, expected) {
+            Err(IntegrityError::HashMismatch {
+                expected: e,
+                actual,
+            }) => {
+                assert_eq!(e, expected);
+                assert_eq!(actual, Sha256Digest::of_file(&path).unwrap());
+            }
+            other => panic!("expected a hash mismatch, got {:?}", other),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn parse_manifest_reads_sha256sum_and_bare_digests() {
+        let expected: Sha256Digest = EMPTY_SHA256.parse().unwrap();
+        assert_eq!(parse_manifest(EMPTY_SHA256), Some(expected));
+        let line = format!("{}  screen_record.exe\n", EMPTY_SHA256);
+        assert_eq!(parse_manifest(&line), Some(expected));
+    }
+
+    #[test]
+    fn parse_manifest_rejects_malformed_manifests() {
+        assert_eq!(parse_manifest(""), None);
+        assert_eq!(parse_manifest("not a digest  screen_record.exe"), None);
+        // One hex digit short, and one byte too long
+        assert_eq!(parse_manifest(&EMPTY_SHA256[1..]), None);
+        assert_eq!(parse_manifest(&format!("{}00", EMPTY_SHA256)), None);
+    }
+
+    #[test]
+    fn manifest_key_rejects_bad_signatures() {
+        let signing = SigningKey::from_bytes(&[7; 32]);
+        let key: ManifestKey = BASE64
+            .encode(signing.verifying_key().as_bytes())
+            .parse()
+            .unwrap();
+        let manifest = format!("{}  screen_record.exe\n", EMPTY_SHA256);
+        let signature = BASE64.encode(signing.sign(manifest.as_bytes()).to_bytes());
+
+        assert!(key.verify(manifest.as_bytes(), &signature));
+        assert!(!key.verify(b"tampered manifest", &signature));
+        let other = SigningKey::from_bytes(&[8; 32]);
+        let forged = BASE64.encode(other.sign(manifest.as_bytes()).to_bytes());
+        assert!(!key.verify(manifest.as_bytes(), &forged));
+        assert!(!key.verify(manifest.as_bytes(), "not base64!"));
+        assert!(!key.verify(manifest.as_bytes(), &BASE64.encode([0u8; 10])));
+    }
+
+    #[test]
+    fn manifest_key_rejects_malformed_keys() {
+        assert!("".parse::<ManifestKey>().is_err());
+        assert!(BASE64.encode([1u8; 31]).parse::<ManifestKey>().is_err());
+    }
+}
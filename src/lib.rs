@@ -1,2 +1,14 @@
+pub mod agent_config;
+pub mod config;
+pub mod doctor;
+pub mod error;
+pub mod events;
+pub mod integrity;
 pub mod modules;
-pub mod run;
\ No newline at end of file
+pub mod process;
+pub mod proxy;
+pub mod queue;
+pub mod recorder;
+pub mod run;
+pub mod tls;
+pub mod updater;
@@ -1,33 +1,412 @@
-use anyhow::Result;
-use screen_record::run::process_screen_recording;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use clap::{value_parser, Arg, ArgMatches, Args, FromArgMatches, Parser, Subcommand, ValueEnum};
+use screen_record::agent_config::{AgentConfig, ValueKind, SETTINGS};
+use screen_record::doctor::run_doctor;
+use screen_record::error::ScreenRecordError;
+use screen_record::modules::components::record_screen::record_screen_fl::list_displays;
+use screen_record::queue::{self, UploadQueue};
+use screen_record::run::{stop_recorder, AppDirs, RecordingSession};
+use screen_record::updater::{self, InstalledRecorders};
+use tokio_util::sync::CancellationToken;
+
+/// Used by `stop` when `stop_timeout_secs` is not a valid number.
+const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Used by `queue purge` when `segment_duration_secs` is not a valid number.
+const DEFAULT_SEGMENT_DURATION: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Screen recording agent")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+
+    /// Print the effective configuration and where each value came from, then exit
+    #[arg(long, global = true)]
+    print_config: bool,
+
+    /// Log line format; the level filter comes from RUST_LOG (default: info)
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Record, upload and notify in a continuous loop (the default)
+    Run,
+    /// Record a single segment, upload it and exit
+    Once,
+    /// Upload an existing recording and notify the API
+    Upload { file: PathBuf },
+    /// Stop the recorder processes started by this user's agents
+    Stop,
+    /// Check the app directory, temp directory and recorder executable
+    Doctor,
+    /// List the displays the native recorder can capture
+    Displays,
+    /// Inspect or work through the on-disk upload queue
+    Queue {
+        #[command(subcommand)]
+        action: QueueCommand,
+    },
+    /// Manage recorder versions installed from the recorder manifest
+    Recorder {
+        #[command(subcommand)]
+        action: RecorderCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum QueueCommand {
+    /// List queued segments and their state
+    List,
+    /// Upload every queued segment now, including failed ones (run while the agent is stopped)
+    Retry,
+    /// Delete every queued segment (run while the agent is stopped)
+    Purge,
+}
+
+#[derive(Debug, Subcommand)]
+enum RecorderCommand {
+    /// Show the active and previous recorder version
+    Status,
+    /// Check the recorder manifest now and switch to its version
+    Update,
+    /// Switch back to the previous recorder version
+    Rollback,
+}
+
+/// `--config` and one flag per setting in [`SETTINGS`].
+#[derive(Debug)]
+struct ConfigArgs {
+    /// Config file to use instead of config.toml/config.json in the app directory
+    config: Option<PathBuf>,
+    /// Flags that were given, as `(config key, value)` pairs.
+    overrides: Vec<(&'static str, String)>,
+}
+
+impl FromArgMatches for ConfigArgs {
+    fn from_arg_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        let overrides = SETTINGS
+            .iter()
+            .filter_map(|spec| {
+                let value = match spec.kind {
+                    ValueKind::Bool => matches.get_one::<bool>(spec.key)?.to_string(),
+                    ValueKind::Number => matches.get_one::<u64>(spec.key)?.to_string(),
+                    _ => matches.get_one::<String>(spec.key)?.clone(),
+                };
+                Some((spec.key, value))
+            })
+            .collect();
+        Ok(Self {
+            config: matches.get_one::<PathBuf>("config").cloned(),
+            overrides,
+        })
+    }
+
+    fn update_from_arg_matches(&mut self, matches: &ArgMatches) -> Result<(), clap::Error> {
+        *self = Self::from_arg_matches(matches)?;
+        Ok(())
+    }
+}
+
+impl Args for ConfigArgs {
+    fn augment_args(command: clap::Command) -> clap::Command {
+        let config = Arg::new("config")
+            .long("config")
+            .global(true)
+            .value_name("PATH")
+            .value_parser(value_parser!(PathBuf))
+            .help("Config file to use instead of config.toml/config.json in the app directory");
+        SETTINGS.iter().fold(command.arg(config), |command, spec| {
+            let arg = Arg::new(spec.key)
+                .long(spec.key.replace('_', "-"))
+                .global(true)
+                .value_name(spec.value_name)
+                .help(spec.help);
+            command.arg(match spec.kind {
+                ValueKind::Bool => arg.value_parser(value_parser!(bool)),
+                ValueKind::Number => arg.value_parser(value_parser!(u64)),
+                _ => arg.value_parser(value_parser!(String)),
+            })
+        })
+    }
+
+    fn augment_args_for_update(command: clap::Command) -> clap::Command {
+        Self::augment_args(command)
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    env_logger::init();
-
-    loop {
-        if let Err(e) = process_screen_recording(
-            "fb01503c-0302-4033-9b0b-ab737ae1875f",
-            "https://app.trackforce.io/api/TrackerDesktop/AddWebCamEvent",
-            "https://screcord_app.ibos.io/screen_record.exe",
-            "23.98.93.20",
-            "50057",
-        )
-        .await
-        {
-            eprintln!("Error: {}", e);
+    let cli = Cli::parse();
+    init_logging(cli.log_format);
+    let agent_config = AgentConfig::load(cli.config.config.as_deref(), &cli.config.overrides)?;
+
+    if cli.print_config {
+        match agent_config.config_file() {
+            Some(path) => println!("# config file: {}", path.display()),
+            None => println!("# config file: none"),
+        }
+        print!("{}", agent_config.render());
+        return Ok(());
+    }
+
+    // Only the commands that record or upload need a valid recording
+    // configuration
+    let session = || -> Result<(RecordingSession, CancellationToken)> {
+        let session = RecordingSession::new(agent_config.to_recording_config()?)?;
+        Ok((session, shutdown_token()))
+    };
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => {
+            let (session, cancel) = session()?;
+            exit_on_error(session.run_continuous(&cancel).await)
+        }
+        Command::Once => {
+            let (session, cancel) = session()?;
+            match session.record_segment_until(&cancel).await {
+                Err(e @ ScreenRecordError::Cancelled { .. }) => {
+                    report_cancelled(&e);
+                    Ok(())
+                }
+                result => exit_on_error(result),
+            }
         }
+        Command::Upload { file } => exit_on_error(session()?.0.upload_file(&file).await),
+        Command::Stop => {
+            let timeout = agent_config
+                .get("stop_timeout_secs")
+                .and_then(|setting| setting.value.trim().parse().ok())
+                .map_or(DEFAULT_STOP_TIMEOUT, Duration::from_secs);
+            let dirs = AppDirs::locate()?;
+            let stopped = stop_recorder(&dirs, timeout).await?;
+            println!("Stopped {} recorder process(es)", stopped);
+            Ok(())
+        }
+        Command::Doctor => {
+            let config = agent_config.to_recording_config();
+            let report = run_doctor(config.as_ref().map_err(|e| e.to_string()));
+            print!("{}", report);
+            if !report.is_healthy() {
+                bail!("doctor found problems");
+            }
+            Ok(())
+        }
+        Command::Displays => {
+            for display in list_displays()? {
+                println!(
+                    "{}\t{}\t{}x{}",
+                    display.index, display.name, display.width, display.height
+                );
+            }
+            Ok(())
+        }
+        Command::Queue { action } => match action {
+            QueueCommand::List => {
+                let dirs = app_dirs()?;
+                let entries = queue::read_entries(&dirs.app_dir)?;
+                for entry in &entries {
+                    println!(
+                        "{}\t{}\t{} bytes\t{} failed attempt(s)\t{}",
+                        entry.path.display(),
+                        entry.state.as_str(),
+                        entry.size,
+                        entry.attempts,
+                        entry.last_error.as_deref().unwrap_or("-")
+                    );
+                }
+                println!("{} queued segment(s)", entries.len());
+                Ok(())
+            }
+            QueueCommand::Retry => {
+                let remaining = match session()?.0.retry_queue().await {
+                    Ok(remaining) => remaining,
+                    Err(e) => {
+                        report_error(&e);
+                        std::process::exit(1);
+                    }
+                };
+                if remaining > 0 {
+                    bail!(
+                        "{} segment(s) are still queued, see `queue list`",
+                        remaining
+                    );
+                }
+                Ok(())
+            }
+            QueueCommand::Purge => {
+                let dirs = app_dirs()?;
+                let settle = agent_config
+                    .get("segment_duration_secs")
+                    .and_then(|setting| setting.value.trim().parse().ok())
+                    .map_or(DEFAULT_SEGMENT_DURATION, Duration::from_secs);
+                let removed = UploadQueue::open(&dirs.app_dir, &dirs.tmp_dir, settle)?.purge()?;
+                println!("Removed {} queued segment(s)", removed);
+                Ok(())
+            }
+        },
+        Command::Recorder { action } => match action {
+            RecorderCommand::Status => {
+                let dirs = app_dirs()?;
+                let installed = InstalledRecorders::load(&dirs.bin_dir)?;
+                let show = |v: &Option<semver::Version>| {
+                    v.as_ref()
+                        .map_or_else(|| "-".to_string(), |v| v.to_string())
+                };
+                println!("platform\t{}", updater::platform());
+                println!("current\t{}", show(&installed.current));
+                println!("previous\t{}", show(&installed.previous));
+                println!("rejected\t{}", show(&installed.rejected));
+                Ok(())
+            }
+            RecorderCommand::Update => {
+                match session()?.0.update_recorder().await {
+                    Ok(Some(version)) => println!("Recorder {} is now active", version),
+                    Ok(None) => println!("Recorder is up to date"),
+                    Err(e) => exit_on_error(Err(e))?,
+                }
+                Ok(())
+            }
+            RecorderCommand::Rollback => {
+                let dirs = app_dirs()?;
+                match updater::rollback(&dirs.bin_dir) {
+                    Ok(version) => println!("Recorder {} is active again", version),
+                    Err(e) => exit_on_error(Err(e))?,
+                }
+                Ok(())
+            }
+        },
+    }
+}
+
+fn init_logging(format: LogFormat) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+
+    if let LogFormat::Json = format {
+        builder.format(|buf, record| {
+            let mut line = serde_json::Map::new();
+            line.insert("ts".into(), chrono::Utc::now().to_rfc3339().into());
+            line.insert("level".into(), record.level().as_str().into());
+            line.insert("target".into(), record.target().into());
+            line.insert("msg".into(), record.args().to_string().into());
+
+            let mut fields = JsonFields(&mut line);
+            let _ = record.key_values().visit(&mut fields);
+
+            writeln!(buf, "{}", serde_json::Value::Object(line))
+        });
+    }
+
+    builder.init();
+}
+
+/// Copies a record's structured key-values into a JSON object.
+struct JsonFields<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let json = if let Some(n) = value.to_u64() {
+            n.into()
+        } else if let Some(n) = value.to_i64() {
+            n.into()
+        } else if let Some(b) = value.to_bool() {
+            b.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.to_string(), json);
+        Ok(())
+    }
+}
+
+/// Token cancelled on the first Ctrl-C/SIGTERM, which stops the recorder
+/// right away and lets uploads in progress finish; a second signal exits
+/// immediately.
+fn shutdown_token() -> CancellationToken {
+    let token = CancellationToken::new();
+    let cancel = token.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        log::info!(
+            "shutdown requested, stopping the recorder and finishing uploads in progress \
+             (signal again to force)"
+        );
+        cancel.cancel();
+        shutdown_signal().await;
+        log::warn!("forced shutdown");
+        std::process::exit(130);
+    });
+    token
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+fn app_dirs() -> Result<AppDirs> {
+    Ok(AppDirs::locate()?)
+}
+
+fn report_error(e: &ScreenRecordError) {
+    eprintln!("Error: {}", e);
+    if let Some(hint) = e.hint() {
+        eprintln!("Hint: {}", hint);
+    }
+}
+
+fn report_cancelled(e: &ScreenRecordError) {
+    if let ScreenRecordError::Cancelled { kept: Some(path) } = e {
+        eprintln!(
+            "Recording cancelled; partial file kept at {}",
+            path.display()
+        );
+    } else {
+        eprintln!("Recording cancelled");
+    }
+}
+
+fn exit_on_error(result: Result<(), ScreenRecordError>) -> Result<()> {
+    if let Err(e) = result {
+        report_error(&e);
+        std::process::exit(1);
     }
-    // if let Err(e) = process_screen_recording(
-    //     "fb01503c-0302-4033-9b0b-ab737ae1875f",
-    //     "https://app.trackforce.io/api/TrackerDesktop/AddWebCamEvent",
-    //     "23.98.93.20",
-    //     "50057",
-    // )
-    // .await
-    // {
-    //     eprintln!("Error: {}", e);
-    // }
-
-    // Ok(())
+    Ok(())
 }
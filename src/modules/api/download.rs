@@ -1,27 +1,379 @@
-use anyhow::{bail, Result};
-use futures_util::TryStreamExt;
-use reqwest::Client;
-use std::{io::ErrorKind, path::PathBuf};
-use tokio::{fs::File, io::BufWriter};
-use tokio_util::io::StreamReader;
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-pub async fn download_recorder_exe(url: &str, dest: &PathBuf) -> Result<()> {
-    let client = Client::new();
-    let response = client.get(url).send().await?;
+use futures_util::StreamExt;
+use log::{debug, info, warn};
+use reqwest::header::{HeaderValue, CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+use reqwest::{Client, StatusCode};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
 
-    if !response.status().is_success() {
-        bail!("Failed to download file: {}", response.status());
+use crate::events::{EventSender, RecordingEvent};
+use crate::proxy::ProxyConfig;
+use crate::tls::TlsPolicy;
+
+/// Progress events are sent roughly once per this many bytes.
+const PROGRESS_STEP: u64 = 1_000_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum DownloadError {
+    #[error("{0}")]
+    Http(#[from] reqwest::Error),
+
+    #[error("HTTP error: {0}")]
+    Status(StatusCode),
+
+    #[error("server resumed at byte {got} instead of {expected}")]
+    BadRange { expected: u64, got: u64 },
+
+    #[error("connection closed after {received} of {expected} bytes")]
+    Incomplete { received: u64, expected: u64 },
+
+    #[error("failed to write {}: {source}", path.display())]
+    Write { path: PathBuf, source: io::Error },
+}
+
+/// How often and how patiently each URL of a download is retried.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Consecutive attempts on one URL that may fail without getting any
+    /// further before the next URL is tried.
+    pub attempts: u32,
+    /// Pause after the first failure, doubled after every further attempt
+    /// that made no progress.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 4,
+            initial_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Pause after the `stalled`th consecutive attempt without progress.
+    fn delay(&self, stalled: u32) -> Duration {
+        let factor = 2u32.saturating_pow(stalled.saturating_sub(1));
+        self.initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay)
     }
+}
+
+/// What one request of a download did, for the logs and the final error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DownloadAttempt {
+    pub url: String,
+    /// Counted per URL, starting at 1.
+    pub attempt: u32,
+    /// Size of the part file when the request was sent.
+    pub offset: u64,
+    /// Size of the part file afterwards.
+    pub bytes: u64,
+    pub elapsed: Duration,
+    /// `None` for the attempt that completed the download.
+    pub error: Option<String>,
+}
 
-    let byte_stream = response
-        .bytes_stream()
-        .map_err(|e| std::io::Error::new(ErrorKind::Other, e));
-    let mut reader = StreamReader::new(byte_stream);
+impl fmt::Display for DownloadAttempt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} attempt {}", self.url, self.attempt)?;
+        if self.offset > 0 {
+            write!(f, " (from byte {})", self.offset)?;
+        }
+        match &self.error {
+            Some(error) => write!(f, ": {} after {:.1?}", error, self.elapsed)?,
+            None => write!(f, ": complete after {:.1?}", self.elapsed)?,
+        }
+        if self.bytes > self.offset {
+            write!(f, ", {} bytes received", self.bytes - self.offset)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every URL failed; `attempts` lists each request in order.
+#[derive(Debug, thiserror::Error)]
+#[error("{} failed attempts", attempts.len())]
+pub struct DownloadFailed {
+    pub attempts: Vec<DownloadAttempt>,
+}
 
-    let mut writer = BufWriter::new(File::create(dest).await?);
+/// Client for recorder downloads. There is no overall timeout since the
+/// recorder is large; a connection that stops sending is cut by the read
+/// timeout and resumed.
+pub fn download_client(tls: &TlsPolicy, proxy: &ProxyConfig) -> reqwest::Result<Client> {
+    let builder = proxy.apply(Client::builder());
+    tls.apply(builder, "recorder download")
+        .connect_timeout(Duration::from_secs(30))
+        .read_timeout(Duration::from_secs(60))
+        .tcp_keepalive(Duration::from_secs(20))
+        .user_agent(concat!("screen_record/", env!("CARGO_PKG_VERSION")))
+        .build()
+}
+
+/// Stream the first of `urls` that works into `part`, continuing after
+/// whatever an earlier attempt left there, and return the file's size.
+///
+/// The URLs are mirrors of the same file and are tried in order. Each is
+/// retried with exponential backoff, and interrupted transfers are resumed
+/// with a `Range` request, also on the next mirror. A URL is given up once
+/// `retry.attempts` consecutive attempts made no progress or the server
+/// answers with a client error. The result is complete when it matches the
+/// server's `Content-Length`; on error `part` is kept so the next call can
+/// resume.
+///
+/// The `ETag` or `Last-Modified` of the response that started `part` is kept
+/// next to it and sent as `If-Range`, so a file that changed on the server
+/// in the meantime is downloaded again from the start instead of being
+/// appended to the old one.
+pub async fn download_recorder_exe(
+    client: &Client,
+    urls: &[&str],
+    part: &Path,
+    retry: &RetryPolicy,
+    events: &EventSender,
+) -> Result<u64, DownloadFailed> {
+    let mut attempts = Vec::new();
+    for (index, &url) in urls.iter().enumerate() {
+        let mut stalled = 0;
+        for attempt in 1.. {
+            let offset = part_len(part).await;
+            let started = Instant::now();
+            let result = download_once(client, url, part, events).await;
+            let bytes = part_len(part).await;
+            let mut report = DownloadAttempt {
+                url: url.to_string(),
+                attempt,
+                offset,
+                bytes,
+                elapsed: started.elapsed(),
+                error: None,
+            };
+
+            let e = match result {
+                Ok(size) => {
+                    remove_validator(part).await;
+                    info!(url, path:% = part.display(), bytes = size, attempt; "download complete");
+                    return Ok(size);
+                }
+                Err(e) => e,
+            };
+            report.error = Some(e.to_string());
+            warn!(
+                url,
+                attempt,
+                offset,
+                bytes,
+                elapsed:? = report.elapsed,
+                error:% = e;
+                "download attempt failed"
+            );
+            attempts.push(report);
+
+            // The disk is the problem, another server will not help
+            if let DownloadError::Write { .. } = e {
+                return Err(DownloadFailed { attempts });
+            }
+            stalled = if bytes > offset { 0 } else { stalled + 1 };
+            if !e.is_retryable() || stalled >= retry.attempts {
+                break;
+            }
+            let delay = retry.delay(stalled);
+            events.emit(RecordingEvent::RecorderDownloadRetry {
+                url: url.to_string(),
+                attempt,
+                delay,
+                error: e.to_string(),
+            });
+            tokio::time::sleep(delay).await;
+        }
+        if let Some(next) = urls.get(index + 1) {
+            warn!(url, next = *next; "giving up on download URL, trying the next mirror");
+        }
+    }
+    Err(DownloadFailed { attempts })
+}
 
-    tokio::io::copy(&mut reader, &mut writer).await?;
+impl DownloadError {
+    /// Whether the same URL may succeed when asked again.
+    fn is_retryable(&self) -> bool {
+        match self {
+            DownloadError::Http(_)
+            | DownloadError::Incomplete { .. }
+            | DownloadError::BadRange { .. } => true,
+            DownloadError::Status(status) => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            DownloadError::Write { .. } => false,
+        }
+    }
+}
+
+async fn part_len(part: &Path) -> u64 {
+    tokio::fs::metadata(part)
+        .await
+        .map(|m| m.len())
+        .unwrap_or(0)
+}
+
+/// `<part>.validator`, holding the `If-Range` value for resuming `part`.
+fn validator_path(part: &Path) -> PathBuf {
+    let mut path = OsString::from(part);
+    path.push(".validator");
+    PathBuf::from(path)
+}
+
+async fn read_validator(part: &Path) -> Option<HeaderValue> {
+    let bytes = tokio::fs::read(validator_path(part)).await.ok()?;
+    HeaderValue::from_bytes(&bytes).ok()
+}
+
+/// Keep the response's strong `ETag`, or else its `Last-Modified`, for the
+/// next resume of `part`. Weak ETags cannot be used with `If-Range`.
+async fn save_validator(part: &Path, response: &reqwest::Response) -> io::Result<()> {
+    let headers = response.headers();
+    let validator = headers
+        .get(ETAG)
+        .filter(|etag| !etag.as_bytes().starts_with(b"W/"))
+        .or_else(|| headers.get(LAST_MODIFIED));
+    match validator {
+        Some(value) => tokio::fs::write(validator_path(part), value.as_bytes()).await,
+        None => {
+            remove_validator(part).await;
+            Ok(())
+        }
+    }
+}
+
+async fn remove_validator(part: &Path) {
+    let _ = tokio::fs::remove_file(validator_path(part)).await;
+}
+
+/// One request: resume from the end of `part` when it has data, start over
+/// when the server ignores the range or the file changed.
+async fn download_once(
+    client: &Client,
+    url: &str,
+    part: &Path,
+    events: &EventSender,
+) -> Result<u64, DownloadError> {
+    let write_error = |source| DownloadError::Write {
+        path: part.to_path_buf(),
+        source,
+    };
+
+    let offset = part_len(part).await;
+    let mut request = client.get(url);
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+        if let Some(validator) = read_validator(part).await {
+            request = request.header(IF_RANGE, validator);
+        }
+    }
+    let response = request.send().await?;
+    let status = response.status();
+
+    let (mut file, start) = match status {
+        StatusCode::PARTIAL_CONTENT => {
+            let got = content_range_start(&response).unwrap_or(0);
+            if got != offset {
+                tokio::fs::remove_file(part).await.map_err(write_error)?;
+                remove_validator(part).await;
+                return Err(DownloadError::BadRange {
+                    expected: offset,
+                    got,
+                });
+            }
+            debug!(url, offset; "resuming download");
+            let file = OpenOptions::new()
+                .append(true)
+                .open(part)
+                .await
+                .map_err(write_error)?;
+            (file, offset)
+        }
+        StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => {
+            // Either the part file is already complete or it is longer than
+            // the file now on the server
+            if content_range_total(&response) == Some(offset) {
+                return Ok(offset);
+            }
+            tokio::fs::remove_file(part).await.map_err(write_error)?;
+            remove_validator(part).await;
+            return Err(DownloadError::Incomplete {
+                received: 0,
+                expected: content_range_total(&response).unwrap_or(0),
+            });
+        }
+        status if status.is_success() => {
+            if offset > 0 {
+                debug!(url, offset; "server ignored the range or the file changed, restarting download");
+            }
+            let file = File::create(part).await.map_err(write_error)?;
+            save_validator(part, &response).await.map_err(write_error)?;
+            (file, 0)
+        }
+        status => return Err(DownloadError::Status(status)),
+    };
+
+    let total = response.content_length().map(|len| start + len);
+    let mut bytes = start;
+    let mut last_reported = bytes;
+    events.emit(RecordingEvent::RecorderDownloading { bytes, total });
+
+    let mut body = response.bytes_stream();
+    let result = async {
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk?;
+            file.write_all(&chunk).await.map_err(write_error)?;
+            bytes += chunk.len() as u64;
+            if bytes - last_reported >= PROGRESS_STEP {
+                events.emit(RecordingEvent::RecorderDownloading { bytes, total });
+                last_reported = bytes;
+            }
+        }
+        Ok::<_, DownloadError>(())
+    }
+    .await;
+    // Keep what arrived for the next attempt, even after an error
+    file.flush().await.map_err(write_error)?;
+    file.sync_all().await.map_err(write_error)?;
+    events.emit(RecordingEvent::RecorderDownloading { bytes, total });
+    result?;
+
+    match total {
+        Some(expected) if bytes != expected => Err(DownloadError::Incomplete {
+            received: bytes,
+            expected,
+        }),
+        _ => Ok(bytes),
+    }
+}
+
+/// Start of a `Content-Range: bytes <start>-<end>/<total>` header.
+fn content_range_start(response: &reqwest::Response) -> Option<u64> {
+    let range = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    range
+        .strip_prefix("bytes ")?
+        .split('-')
+        .next()?
+        .trim()
+        .parse()
+        .ok()
+}
 
-    println!("✅ Download complete: {}", dest.display());
-    Ok(())
+/// Total of a `Content-Range` header, also in the `bytes */<total>` form
+/// sent with 416.
+fn content_range_total(response: &reqwest::Response) -> Option<u64> {
+    let range = response.headers().get(CONTENT_RANGE)?.to_str().ok()?;
+    range.rsplit('/').next()?.trim().parse().ok()
 }
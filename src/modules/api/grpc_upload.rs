@@ -0,0 +1,327 @@
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, info};
+use sha2::{Digest, Sha256};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity, Uri};
+use tonic::Code;
+use tower::service_fn;
+
+use crate::config::GrpcTls;
+use crate::proxy::{connect_tunnel, ProxyConfig};
+
+pub mod proto {
+    tonic::include_proto!("upload");
+}
+
+use proto::upload_request::Type;
+use proto::upload_service_client::UploadServiceClient;
+use proto::{QueryOffsetRequest, UploadChunk, UploadHeader, UploadRequest};
+
+/// How long an HTTP/2 keepalive ping may go unanswered before the
+/// connection is considered dead.
+const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("failed to read certificate file {}: {source}", path.display())]
+    Certificate { path: PathBuf, source: io::Error },
+
+    #[error("invalid TLS configuration: {0}")]
+    Tls(#[source] tonic::transport::Error),
+
+    #[error("upload rejected ({}): {}", .0.code(), .0.message())]
+    Status(Box<tonic::Status>),
+
+    #[error("failed to read {}: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+
+    #[error("server has {offset} bytes of a {size} byte upload")]
+    BadOffset { offset: u64, size: u64 },
+
+    #[error("server stored {received} of {size} bytes")]
+    Incomplete { received: u64, size: u64 },
+}
+
+impl From<tonic::Status> for UploadError {
+    fn from(status: tonic::Status) -> Self {
+        UploadError::Status(Box::new(status))
+    }
+}
+
+/// Id the server files a resumable upload under; the same file gets the
+/// same id on every retry.
+pub fn upload_id(name: &str, size: u64) -> String {
+    format!("{}-{}", name, size)
+}
+
+/// Tuning for an [`Uploader`].
+#[derive(Debug, Clone)]
+pub struct UploadOptions {
+    /// Bytes of the file per message; must leave room for the message
+    /// framing within `max_message_size`.
+    pub chunk_size: usize,
+    pub connect_timeout: Duration,
+    /// Limit for one RPC, i.e. for sending a whole file.
+    pub request_timeout: Duration,
+    /// HTTP/2 ping and TCP keepalive interval; `None` disables both.
+    pub keepalive: Option<Duration>,
+    /// Largest message sent or accepted.
+    pub max_message_size: usize,
+    /// Required for an `https` endpoint.
+    pub tls: Option<GrpcTls>,
+    /// Proxies are reached with an HTTP `CONNECT` tunnel.
+    pub proxy: ProxyConfig,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: 1024 * 1024,
+            connect_timeout: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(600),
+            keepalive: Some(Duration::from_secs(30)),
+            max_message_size: 16 * 1024 * 1024,
+            tls: None,
+            proxy: ProxyConfig::none(),
+        }
+    }
+}
+
+/// Client for the `UploadService` in `proto/upload.proto`.
+///
+/// The channel connects lazily and is shared by clones, so one `Uploader`
+/// serves every segment and reconnects by itself after the server or the
+/// network went away.
+#[derive(Debug, Clone)]
+pub struct Uploader {
+    client: UploadServiceClient<Channel>,
+    endpoint: Uri,
+    chunk_size: usize,
+}
+
+impl Uploader {
+    /// Must be called from within a Tokio runtime. Fails when a certificate
+    /// file cannot be read or TLS is asked for on a plain `http` endpoint.
+    pub fn new(endpoint: Uri, options: UploadOptions) -> Result<Self, UploadError> {
+        let mut channel = Endpoint::from(endpoint.clone())
+            .connect_timeout(options.connect_timeout)
+            .timeout(options.request_timeout)
+            .tcp_keepalive(options.keepalive);
+        if let Some(interval) = options.keepalive {
+            channel = channel
+                .http2_keep_alive_interval(interval)
+                .keep_alive_timeout(KEEPALIVE_TIMEOUT)
+                .keep_alive_while_idle(true);
+        }
+        if let Some(tls) = &options.tls {
+            channel = channel
+                .tls_config(client_tls_config(tls)?)
+                .map_err(UploadError::Tls)?;
+        }
+
+        let scheme = endpoint.scheme_str().unwrap_or("http");
+        let host = endpoint.host().unwrap_or_default();
+        let channel = match options.proxy.proxy_for(scheme, host) {
+            Some(proxy) => {
+                info!(proxy:% = proxy, endpoint:% = endpoint; "uploading through proxy");
+                let credentials = options.proxy.credentials(&proxy);
+                channel.connect_with_connector_lazy(service_fn(move |target: Uri| {
+                    let (proxy, credentials) = (proxy.clone(), credentials.clone());
+                    async move { connect_tunnel(&proxy, credentials, &target).await }
+                }))
+            }
+            None => channel.connect_lazy(),
+        };
+
+        let client = UploadServiceClient::new(channel)
+            .max_encoding_message_size(options.max_message_size)
+            .max_decoding_message_size(options.max_message_size);
+
+        Ok(Self {
+            client,
+            endpoint,
+            chunk_size: options.chunk_size.max(1),
+        })
+    }
+
+    pub fn endpoint(&self) -> &Uri {
+        &self.endpoint
+    }
+
+    /// Upload `path`, continuing from the offset the server reports for the
+    /// file's [`upload_id`] so a retry does not resend what already arrived.
+    /// Servers without `QueryOffset` get a legacy upload from byte zero.
+    ///
+    /// `progress(sent, total)` is called after every chunk with the absolute
+    /// position in the file.
+    pub async fn upload(
+        &self,
+        path: &Path,
+        progress: impl FnMut(u64, u64) + Send + 'static,
+    ) -> Result<(), UploadError> {
+        let read_error = |source| UploadError::Read {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut file = File::open(path).await.map_err(read_error)?;
+        let size = file.metadata().await.map_err(read_error)?.len();
+        let id = upload_id(&name, size);
+
+        let mut client = self.client.clone();
+        let resumable = match client
+            .query_offset(QueryOffsetRequest {
+                upload_id: id.clone(),
+            })
+            .await
+        {
+            Ok(response) => Some(response.into_inner().offset),
+            Err(status) if status.code() == Code::Unimplemented => None,
+            Err(status) => return Err(status.into()),
+        };
+
+        let offset = resumable.unwrap_or(0);
+        if offset > size {
+            return Err(UploadError::BadOffset { offset, size });
+        }
+        if offset > 0 {
+            info!(upload_id = id.as_str(), offset, total = size; "resuming upload");
+        } else if resumable.is_none() {
+            debug!(upload_id = id.as_str(); "server does not support resuming, using a legacy upload");
+        }
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(read_error)?;
+
+        let first = match resumable {
+            Some(_) => Type::Header(UploadHeader {
+                upload_id: id,
+                name,
+                offset,
+                total_size: size,
+            }),
+            None => Type::Name(name),
+        };
+        let failed_read = Arc::new(Mutex::new(None));
+        let requests = chunks(
+            file,
+            first,
+            Position {
+                offset,
+                size,
+                chunk_size: self.chunk_size,
+            },
+            resumable.is_some(),
+            progress,
+            Arc::clone(&failed_read),
+        );
+
+        let response = client.upload_file(requests).await?.into_inner();
+
+        if let Some(source) = failed_read.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            return Err(read_error(source));
+        }
+        debug!(message = response.message.as_str(), received = response.received; "upload response");
+        // Legacy servers do not report what they stored
+        if resumable.is_some() && response.received != size {
+            return Err(UploadError::Incomplete {
+                received: response.received,
+                size,
+            });
+        }
+        Ok(())
+    }
+}
+
+fn client_tls_config(tls: &GrpcTls) -> Result<ClientTlsConfig, UploadError> {
+    let read_pem = |path: &PathBuf| {
+        std::fs::read(path).map_err(|source| UploadError::Certificate {
+            path: path.clone(),
+            source,
+        })
+    };
+
+    let mut config = ClientTlsConfig::new().with_native_roots();
+    if let Some(path) = &tls.ca_cert {
+        config = config.ca_certificate(Certificate::from_pem(read_pem(path)?));
+    }
+    if let Some(name) = &tls.server_name {
+        config = config.domain_name(name.clone());
+    }
+    if let Some((cert, key)) = &tls.client_identity {
+        config = config.identity(Identity::from_pem(read_pem(cert)?, read_pem(key)?));
+    }
+    Ok(config)
+}
+
+/// Where the request stream starts and how it is cut.
+struct Position {
+    offset: u64,
+    size: u64,
+    chunk_size: usize,
+}
+
+/// The request stream: `first`, then the file from `position.offset` in
+/// `position.chunk_size` pieces. A read error ends the stream early and is
+/// left in `failed_read`.
+fn chunks(
+    mut file: File,
+    first: Type,
+    position: Position,
+    resumable: bool,
+    mut progress: impl FnMut(u64, u64) + Send + 'static,
+    failed_read: Arc<Mutex<Option<io::Error>>>,
+) -> impl tokio_stream::Stream<Item = UploadRequest> + Send + 'static {
+    async_stream::stream! {
+        yield UploadRequest { r#type: Some(first) };
+
+        let Position { offset, size, chunk_size } = position;
+        let mut position = offset;
+        let mut buf = vec![0u8; chunk_size];
+        loop {
+            let read = match read_chunk(&mut file, &mut buf).await {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) => {
+                    *failed_read.lock().unwrap_or_else(|e| e.into_inner()) = Some(e);
+                    break;
+                }
+            };
+            let data = buf[..read].to_vec();
+            let chunk = if resumable {
+                Type::Data(UploadChunk {
+                    offset: position,
+                    sha256: Sha256::digest(&data).to_vec(),
+                    data,
+                })
+            } else {
+                Type::Chunk(data)
+            };
+            yield UploadRequest { r#type: Some(chunk) };
+
+            position += read as u64;
+            progress(position, size);
+        }
+    }
+}
+
+/// Fill `buf` as far as the file allows; returns the bytes read.
+async fn read_chunk(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..]).await? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
@@ -1,2 +1,3 @@
 pub mod download;
+pub mod grpc_upload;
 pub mod upload_video_id_fl;
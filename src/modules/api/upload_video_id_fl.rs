@@ -1,56 +1,69 @@
-use std::path::PathBuf;
+use std::path::Path;
 
-use anyhow::Result;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
 use reqwest::Client;
 use serde_json::json;
 
+use crate::error::{Result, ScreenRecordError};
+
 pub async fn video_id_send_to_api_fn(
     client: &Client,
-    video_id: &PathBuf,
+    video_id: &Path,
     user_id: &str,
     api_url: &str,
+    recorded_at: DateTime<Utc>,
+    recorder_version: Option<&str>,
 ) -> Result<()> {
-    println!("Sending video Id to the API...");
     let file_name = if let Some(name) = video_id.file_name().and_then(|name| name.to_str()) {
-        println!("Video ID: {}", name);
         name.to_string()
     } else {
-        println!(
-            "Failed to extract video ID from path: {}",
-            video_id.display()
-        );
-        return Err(anyhow::anyhow!("Failed to extract video ID from path"));
+        return Err(ScreenRecordError::ApiNotify {
+            status: None,
+            message: format!("no file name in {}", video_id.display()),
+        });
     };
 
     let mut headers = reqwest::header::HeaderMap::new();
-    headers.insert("Content-Type", "application/json".parse()?);
+    headers.insert(
+        reqwest::header::CONTENT_TYPE,
+        reqwest::header::HeaderValue::from_static("application/json"),
+    );
 
     let payload = json!({
         "employeeId": user_id,
         "accountId": 0,
         "fileId": file_name.to_string(),
-        "createdAt": (Utc::now() + chrono::Duration::hours(6)).to_rfc3339(),
+        // The API has always been sent times shifted by six hours
+        "createdAt": (recorded_at + chrono::Duration::hours(6)).to_rfc3339(),
+        "recorderVersion": recorder_version,
     });
 
-    println!("Payload: {}", payload.to_string());
-    println!("Headers: {:?}", headers);
+    debug!(video_id = file_name.as_str(), api_url, payload:% = payload; "sending video id to API");
 
     let response = client
         .post(api_url)
         .headers(headers)
         .json(&payload)
         .send()
-        .await?;
+        .await
+        .map_err(|e| ScreenRecordError::ApiNotify {
+            status: e.status().map(|s| s.as_u16()),
+            message: e.to_string(),
+        })?;
 
     if response.status().is_success() {
-        println!("✅ Video ID sent successfully: {}", video_id.display());
+        info!(video_id = file_name.as_str(); "video id sent");
         Ok(())
     } else {
-        eprintln!("⚠️ Failed to send video ID: {}", response.status());
-        Err(anyhow::anyhow!(
-            "Failed to send video ID: {}",
-            response.status()
-        ))
+        warn!(
+            video_id = file_name.as_str(),
+            status = response.status().as_u16();
+            "API rejected video id"
+        );
+        Err(ScreenRecordError::ApiNotify {
+            status: Some(response.status().as_u16()),
+            message: format!("Failed to send video ID: {}", response.status()),
+        })
     }
 }
@@ -0,0 +1,268 @@
+use scrap::{Capturer, Display};
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::ops::Deref;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::config::{CaptureSourceKind, DisplaySelection, Resolution};
+
+/// Width of the moving bar in the test pattern, in pixels.
+const BAR_WIDTH: usize = 16;
+/// Side of one timestamp bit in the test pattern, in pixels.
+const BIT_SIZE: usize = 8;
+/// Milliseconds the test pattern's bar takes to cross the frame.
+const BAR_PERIOD_MS: u128 = 4_000;
+
+/// A frame borrowed from a [`CaptureSource`]: BGRA rows, possibly padded
+/// beyond `width * 4` bytes.
+pub enum CaptureFrame<'a> {
+    Display(scrap::Frame<'a>),
+    Buffer(&'a [u8]),
+}
+
+impl Deref for CaptureFrame<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            CaptureFrame::Display(frame) => frame,
+            CaptureFrame::Buffer(buffer) => buffer,
+        }
+    }
+}
+
+/// Something `record_screen` can take frames from: a real display, or a
+/// synthetic source for machines without one.
+pub trait CaptureSource {
+    fn name(&self) -> &str;
+
+    fn width(&self) -> usize;
+
+    fn height(&self) -> usize;
+
+    /// The current frame, or an error of kind `WouldBlock` when nothing
+    /// changed since the last call.
+    fn frame(&mut self) -> io::Result<CaptureFrame<'_>>;
+}
+
+/// The sources `kind` describes. Displays are picked by `selection`;
+/// synthetic sources ignore it and produce `resolution` frames.
+pub fn open_sources(
+    kind: &CaptureSourceKind,
+    selection: &DisplaySelection,
+    resolution: Resolution,
+) -> io::Result<Vec<Box<dyn CaptureSource>>> {
+    let (width, height) = (resolution.width as usize, resolution.height as usize);
+    Ok(match kind {
+        CaptureSourceKind::Display => DisplaySource::open(selection)?
+            .into_iter()
+            .map(|source| Box::new(source) as Box<dyn CaptureSource>)
+            .collect(),
+        CaptureSourceKind::TestPattern => vec![Box::new(TestPattern::new(width, height))],
+        CaptureSourceKind::RawFile(path) => {
+            vec![Box::new(RawFileSource::open(path, width, height)?)]
+        }
+    })
+}
+
+/// `display1`, `display2`, ... for the display at `index`.
+pub fn display_name(index: usize) -> String {
+    format!("display{}", index + 1)
+}
+
+/// A display captured with `scrap`.
+pub struct DisplaySource {
+    capturer: Capturer,
+    name: String,
+}
+
+impl DisplaySource {
+    /// Open the displays picked by `selection`, failing when none match.
+    pub fn open(selection: &DisplaySelection) -> io::Result<Vec<Self>> {
+        let displays: Vec<(String, Display)> = match selection {
+            DisplaySelection::Primary => vec![("primary".to_string(), Display::primary()?)],
+            _ => Display::all()?
+                .into_iter()
+                .enumerate()
+                .filter(|(index, _)| match selection {
+                    DisplaySelection::Index(wanted) => index == wanted,
+                    DisplaySelection::Name(name) => display_name(*index).eq_ignore_ascii_case(name),
+                    _ => true,
+                })
+                .map(|(index, display)| (display_name(index), display))
+                .collect(),
+        };
+        if displays.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("no display `{}`, see the `displays` command", selection),
+            ));
+        }
+        displays
+            .into_iter()
+            .map(|(name, display)| {
+                Ok(Self {
+                    capturer: Capturer::new(display)?,
+                    name,
+                })
+            })
+            .collect()
+    }
+}
+
+impl CaptureSource for DisplaySource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn width(&self) -> usize {
+        self.capturer.width()
+    }
+
+    fn height(&self) -> usize {
+        self.capturer.height()
+    }
+
+    fn frame(&mut self) -> io::Result<CaptureFrame<'_>> {
+        self.capturer.frame().map(CaptureFrame::Display)
+    }
+}
+
+/// A synthetic source that always has a new frame: a colour gradient with a
+/// white bar sweeping across it and, in the top-left corner, the
+/// milliseconds since the source was created as a row of 32 black (0) and
+/// white (1) squares, most significant bit first.
+pub struct TestPattern {
+    width: usize,
+    height: usize,
+    started: Instant,
+    background: Vec<u8>,
+    frame: Vec<u8>,
+}
+
+impl TestPattern {
+    pub fn new(width: usize, height: usize) -> Self {
+        let mut background = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let blue = (x * 255 / width.max(1)) as u8;
+                let green = (y * 255 / height.max(1)) as u8;
+                background.extend_from_slice(&[blue, green, 0x40, 0xff]);
+            }
+        }
+        Self {
+            width,
+            height,
+            started: Instant::now(),
+            frame: background.clone(),
+            background,
+        }
+    }
+
+    /// Set a rectangle to the grey level `value`, cropped to the frame.
+    fn fill(&mut self, left: usize, top: usize, width: usize, height: usize, value: u8) {
+        let left = left.min(self.width);
+        let right = (left + width).min(self.width);
+        for y in top..(top + height).min(self.height) {
+            let row = &mut self.frame[(y * self.width + left) * 4..(y * self.width + right) * 4];
+            for pixel in row.chunks_exact_mut(4) {
+                pixel[..3].fill(value);
+            }
+        }
+    }
+}
+
+impl CaptureSource for TestPattern {
+    fn name(&self) -> &str {
+        "test-pattern"
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn frame(&mut self) -> io::Result<CaptureFrame<'_>> {
+        let millis = self.started.elapsed().as_millis();
+        self.frame.copy_from_slice(&self.background);
+
+        let bar = (millis % BAR_PERIOD_MS) as usize * self.width / BAR_PERIOD_MS as usize;
+        self.fill(bar, 0, BAR_WIDTH, self.height, 0xff);
+        let stamp = millis as u32;
+        for bit in 0..32 {
+            let value = if stamp & (1 << (31 - bit)) != 0 {
+                0xff
+            } else {
+                0
+            };
+            self.fill(bit * BIT_SIZE, 0, BIT_SIZE, BIT_SIZE, value);
+        }
+        Ok(CaptureFrame::Buffer(&self.frame))
+    }
+}
+
+/// A synthetic source replaying a file of back-to-back raw BGRA frames, one
+/// per call, starting over at the end of the file.
+pub struct RawFileSource {
+    file: File,
+    width: usize,
+    height: usize,
+    frame: Vec<u8>,
+}
+
+impl RawFileSource {
+    pub fn open(path: &Path, width: usize, height: usize) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let frame_size = width * height * 4;
+        let len = file.metadata()?.len();
+        if frame_size == 0 || len < frame_size as u64 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} holds {} bytes, less than one {}x{} BGRA frame",
+                    path.display(),
+                    len,
+                    width,
+                    height
+                ),
+            ));
+        }
+        Ok(Self {
+            file,
+            width,
+            height,
+            frame: vec![0; frame_size],
+        })
+    }
+}
+
+impl CaptureSource for RawFileSource {
+    fn name(&self) -> &str {
+        "raw-file"
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn frame(&mut self) -> io::Result<CaptureFrame<'_>> {
+        match self.file.read_exact(&mut self.frame) {
+            Ok(()) => {}
+            // A trailing partial frame is skipped along with the end
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                self.file.seek(SeekFrom::Start(0))?;
+                self.file.read_exact(&mut self.frame)?;
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(CaptureFrame::Buffer(&self.frame))
+    }
+}
@@ -1 +1,2 @@
-pub mod record_screen_fl;
\ No newline at end of file
+pub mod capture_source_fl;
+pub mod record_screen_fl;
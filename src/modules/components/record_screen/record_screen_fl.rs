@@ -1,42 +1,353 @@
-use scrap::{Capturer, Display};
-use std::fs::File;
-use std::io::Write;
-use std::path::PathBuf;
+use log::{info, warn};
+use scrap::Display;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
+use crate::config::DisplayLayout;
+use crate::modules::components::record_screen::capture_source_fl::{display_name, CaptureSource};
+use crate::modules::components::video_conversion::video_conversion_fl::{ffmpeg_path, mux_tracks};
+use crate::modules::components::video_conversion::video_encoder_fl::FrameEncoder;
+
+/// A display [`record_screen`] can capture through a
+/// [`DisplaySource`](super::capture_source_fl::DisplaySource).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayInfo {
+    /// Position in the platform's display enumeration.
+    pub index: usize,
+    /// `display1`, `display2`, ... in enumeration order.
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// The displays attached to this machine, in the order their indices refer
+/// to.
+pub fn list_displays() -> io::Result<Vec<DisplayInfo>> {
+    Ok(Display::all()?
+        .iter()
+        .enumerate()
+        .map(|(index, display)| DisplayInfo {
+            index,
+            name: display_name(index),
+            width: display.width(),
+            height: display.height(),
+        })
+        .collect())
+}
+
+/// What [`record_screen`] captured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureStats {
+    /// Width and height of every video track in the file.
+    pub tracks: Vec<(usize, usize)>,
+    /// Frames in each track, `secs * fps` up to rounding.
+    pub frames: usize,
+    /// Frames, over all tracks, that repeat the previous one because the
+    /// display had nothing new at their deadline or the deadline was
+    /// missed.
+    pub duplicated: usize,
+    /// Deadlines missed because capture or encoding overran; each was filled
+    /// with a duplicate rather than a late frame.
+    pub late: usize,
+    /// Wall-clock time covered by the video.
+    pub secs: f64,
+}
+
+/// Hands out frame deadlines at a fixed rate from a start instant, so
+/// timing errors never accumulate: frame `n` is due at `start + n / fps`
+/// however long the frames before it took.
+#[derive(Debug, Clone)]
+pub struct FramePacer {
+    start: Instant,
+    interval: Duration,
+    next: u64,
+}
+
+impl FramePacer {
+    pub fn new(start: Instant, fps: u32) -> Self {
+        Self {
+            start,
+            interval: Duration::from_secs(1) / fps.max(1),
+            next: 0,
+        }
+    }
+
+    /// When the next frame is due.
+    pub fn deadline(&self) -> Instant {
+        self.start + self.interval * self.next as u32
+    }
+
+    /// Frames due by `now` that have not been handed out yet, marking them
+    /// as handed out. More than one means deadlines were missed.
+    pub fn due(&mut self, now: Instant) -> u64 {
+        let elapsed = now.saturating_duration_since(self.start);
+        let slot = (elapsed.as_nanos() / self.interval.as_nanos().max(1)) as u64;
+        if slot < self.next {
+            return 0;
+        }
+        let due = slot + 1 - self.next;
+        self.next = slot + 1;
+        due
+    }
+
+    /// Frames handed out so far.
+    pub fn frames(&self) -> u64 {
+        self.next
+    }
+
+    /// Video time covered by the frames handed out so far.
+    pub fn elapsed(&self) -> Duration {
+        self.interval * self.next as u32
+    }
+}
+
+/// One capture source and, when compositing, where it sits on the canvas.
+struct Source {
+    source: Box<dyn CaptureSource>,
+    width: usize,
+    height: usize,
+    x: usize,
+}
+
+/// Where captured frames go.
+enum Sink {
+    /// One encoder per source.
+    Tracks(Vec<FrameEncoder>),
+    /// Every source drawn onto one canvas.
+    Canvas {
+        encoder: FrameEncoder,
+        canvas: Vec<u8>,
+        width: usize,
+    },
+}
+
+/// Capture `sources`, usually displays opened with
+/// [`open_sources`](super::capture_source_fl::open_sources), into the video
+/// file `path` for `duration`, or until `stop` is set, at `fps` frames per
+/// second. Frames are encoded by ffmpeg as they are captured.
+///
+/// Several sources are either recorded as one video track each and
+/// combined into `path` at the end ([`DisplayLayout::Separate`]), or drawn
+/// side by side in the given order onto one canvas
+/// ([`DisplayLayout::Composite`]); `scrap` does not report where displays
+/// sit relative to each other.
+///
+/// Frames are taken on fixed deadlines shared by all sources. When a
+/// source has nothing new at a deadline, or capture and encoding overran
+/// past later deadlines, the previous frame is repeated for those slots, so
+/// every track's timeline follows the wall clock.
 pub fn record_screen(
-    path: &PathBuf,
+    path: &Path,
+    sources: Vec<Box<dyn CaptureSource>>,
+    layout: DisplayLayout,
     duration: Duration,
-) -> Result<(usize, usize, usize, f64), Box<dyn std::error::Error>> {
-    let one = Display::primary()?;
-    let mut capturer = Capturer::new(one)?;
-    let (w, h) = (capturer.width(), capturer.height());
+    fps: u32,
+    stop: &AtomicBool,
+) -> Result<CaptureStats, Box<dyn std::error::Error>> {
+    if sources.is_empty() {
+        return Err("nothing to capture".into());
+    }
+    let mut x = 0;
+    let mut sources: Vec<Source> = sources
+        .into_iter()
+        .map(|source| {
+            let (width, height) = (source.width(), source.height());
+            x += width;
+            Source {
+                source,
+                width,
+                height,
+                x: x - width,
+            }
+        })
+        .collect();
+
+    let ffmpeg = ffmpeg_path();
+    let separate = sources.len() == 1 || layout == DisplayLayout::Separate;
+    let track_paths: Vec<PathBuf> = if sources.len() == 1 {
+        vec![path.to_path_buf()]
+    } else {
+        sources
+            .iter()
+            .map(|s| track_path(path, s.source.name()))
+            .collect()
+    };
+    let (mut sink, tracks) = if separate {
+        let encoders = sources
+            .iter()
+            .zip(&track_paths)
+            .map(|(source, track)| {
+                FrameEncoder::spawn(&ffmpeg, track, source.width, source.height, fps)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let tracks = sources.iter().map(|s| (s.width, s.height)).collect();
+        (Sink::Tracks(encoders), tracks)
+    } else {
+        let width = x;
+        let height = sources.iter().map(|s| s.height).max().unwrap_or(0);
+        let encoder = FrameEncoder::spawn(&ffmpeg, path, width, height, fps)?;
+        let canvas = vec![0; width * height * 4];
+        (
+            Sink::Canvas {
+                encoder,
+                canvas,
+                width,
+            },
+            vec![(width, height)],
+        )
+    };
 
-    let mut output = File::create(path)?;
     let start = Instant::now();
-    let mut frame_count = 0;
+    let mut pacer = FramePacer::new(start, fps);
+    let mut duplicated = 0;
+    let mut late = 0;
 
-    while start.elapsed() < duration {
-        match capturer.frame() {
-            Ok(frame) => {
-                output.write_all(&frame)?;
-                frame_count += 1;
+    while pacer.elapsed() < duration && !stop.load(Ordering::Relaxed) {
+        if let Some(wait) = pacer.deadline().checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+        let due = pacer.due(Instant::now());
+        if due == 0 {
+            continue;
+        }
+        // Missed deadlines hold the previous frame
+        for _ in 1..due {
+            duplicated += sink.repeat()?;
+        }
+        late += due as usize - 1;
+
+        duplicated += sink.capture(&mut sources)?;
+    }
+
+    let secs = pacer.elapsed().as_secs_f64();
+    let frames = pacer.frames() as usize;
+    sink.finish()?;
+    if track_paths.len() > 1 && separate {
+        mux_tracks(&track_paths, path)?;
+    }
+    if late > 0 {
+        warn!(late, frames; "capture fell behind, missed frames were filled with duplicates");
+    }
+    info!(
+        sources = sources.len(),
+        layout:% = layout,
+        secs,
+        frames,
+        duplicated,
+        wall_secs = start.elapsed().as_secs_f64(),
+        path:% = path.display();
+        "screen capture finished"
+    );
+    Ok(CaptureStats {
+        tracks,
+        frames,
+        duplicated,
+        late,
+        secs,
+    })
+}
+
+/// `<stem>.<source>.<ext>` next to `path`, for one source's track.
+fn track_path(path: &Path, source: &str) -> PathBuf {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default();
+    path.with_extension(format!("{}.{}", source, extension))
+}
+
+impl Sink {
+    /// Take one frame from every source and send it on. Returns how many
+    /// frames repeated the previous one.
+    fn capture(&mut self, sources: &mut [Source]) -> io::Result<usize> {
+        match self {
+            Sink::Tracks(encoders) => {
+                let mut repeated = 0;
+                for (source, encoder) in sources.iter_mut().zip(encoders) {
+                    match source.source.frame() {
+                        Ok(frame) => encoder.write_frame(&frame)?,
+                        Err(error) if error.kind() == ErrorKind::WouldBlock => {
+                            encoder.repeat_frame()?;
+                            repeated += 1;
+                        }
+                        Err(error) => return Err(error),
+                    }
+                }
+                Ok(repeated)
             }
-            Err(error) => {
-                if error.kind() != std::io::ErrorKind::WouldBlock {
-                    return Err(Box::new(error));
+            Sink::Canvas {
+                encoder,
+                canvas,
+                width,
+            } => {
+                let mut changed = false;
+                for source in sources.iter_mut() {
+                    let (x, w, h) = (source.x, source.width, source.height);
+                    match source.source.frame() {
+                        Ok(frame) => {
+                            blit(canvas, *width, (x, w, h), &frame);
+                            changed = true;
+                        }
+                        Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+                        Err(error) => return Err(error),
+                    }
+                }
+                if changed {
+                    encoder.write_frame(canvas)?;
+                    Ok(0)
+                } else {
+                    encoder.repeat_frame()?;
+                    Ok(1)
                 }
             }
         }
-        thread::sleep(Duration::from_millis(33));
     }
 
-    let actual_secs = start.elapsed().as_secs_f64();
-    println!(
-        "Captured {}x{} for {:.2} seconds with {} frames",
-        w, h, actual_secs, frame_count
-    );
-    Ok((w, h, frame_count, actual_secs))
+    /// Hold the previous frame on every track. Returns the frames sent.
+    fn repeat(&mut self) -> io::Result<usize> {
+        match self {
+            Sink::Tracks(encoders) => {
+                for encoder in encoders.iter_mut() {
+                    encoder.repeat_frame()?;
+                }
+                Ok(encoders.len())
+            }
+            Sink::Canvas { encoder, .. } => encoder.repeat_frame().map(|()| 1),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Sink::Tracks(encoders) => {
+                for encoder in encoders {
+                    encoder.finish()?;
+                }
+                Ok(())
+            }
+            Sink::Canvas { encoder, .. } => encoder.finish().map(|_| ()),
+        }
+    }
+}
+
+/// Copy a `width`x`height` BGRA `frame`, whose rows may be padded, to
+/// column `x` of a canvas `canvas_width` pixels wide.
+fn blit(
+    canvas: &mut [u8],
+    canvas_width: usize,
+    (x, width, height): (usize, usize, usize),
+    frame: &[u8],
+) {
+    let row = width * 4;
+    let stride = frame.len() / height.max(1);
+    if stride < row {
+        return;
+    }
+    for (y, line) in frame.chunks_exact(stride).take(height).enumerate() {
+        let offset = (y * canvas_width + x) * 4;
+        canvas[offset..offset + row].copy_from_slice(&line[..row]);
+    }
 }
@@ -1,3 +1,4 @@
+use log::info;
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::PathBuf;
@@ -44,6 +45,6 @@ pub fn join_mp4_files(
     // Optional cleanup
     fs::remove_file(list_path)?;
 
-    println!("✅ Joined video created at: {}", output_path.display());
+    info!(path:% = output_path.display(), parts = mp4_paths.len(); "joined video created");
     Ok(())
 }
@@ -1,2 +1,3 @@
+pub mod components;
 pub mod video_conversion_fl;
-pub mod components;
\ No newline at end of file
+pub mod video_encoder_fl;
@@ -1,5 +1,6 @@
+use log::{debug, info};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 pub fn convert_raw_to_mp4(
@@ -10,20 +11,17 @@ pub fn convert_raw_to_mp4(
     frames: usize,
     duration_secs: f64,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut frame_rate = (frames as f64 / duration_secs).round() as usize;
-    if frame_rate == 0 {
-        frame_rate = 1;
-    }
-
-    let ffmpeg_exe = if cfg!(debug_assertions) {
-        // In debug mode, use the path to the ffmpeg executable in the project directory
-        PathBuf::from("C:\\ffmpeg\\bin\\ffmpeg.exe")
+    // Keep the fraction: rounding to whole frames per second makes
+    // playback run fast or slow
+    let frame_rate = if frames > 0 && duration_secs > 0.0 {
+        frames as f64 / duration_secs
     } else {
-        // In release mode, use the path to the ffmpeg executable in the same directory as the exe
-        get_ffmpeg_path()
+        1.0
     };
 
-    println!("FFmpeg path: {}", ffmpeg_exe.display());
+    let ffmpeg_exe = ffmpeg_path();
+
+    debug!(ffmpeg:% = ffmpeg_exe.display(); "using ffmpeg");
 
     let status = Command::new(ffmpeg_exe)
         .args([
@@ -34,7 +32,7 @@ pub fn convert_raw_to_mp4(
             "-video_size",
             &format!("{}x{}", width, height),
             "-framerate",
-            &frame_rate.to_string(),
+            &format!("{:.6}", frame_rate),
             "-i",
             raw_path.to_str().unwrap(),
             "-c:v",
@@ -50,11 +48,11 @@ pub fn convert_raw_to_mp4(
         .status()?;
 
     if status.success() {
-        println!("Conversion succeeded!");
+        info!(path:% = mp4_path.display(), frame_rate; "conversion succeeded");
 
         if raw_path.exists() {
             fs::remove_file(raw_path)?;
-            println!("Deleted raw file: {}", raw_path.display());
+            debug!(path:% = raw_path.display(); "deleted raw file");
         }
     }
 
@@ -64,12 +62,55 @@ pub fn convert_raw_to_mp4(
     Ok(())
 }
 
-fn get_ffmpeg_path() -> PathBuf {
-    let exe_dir = std::env::current_exe()
-        .expect("Failed to get current exe path")
-        .parent()
-        .expect("No parent directory for exe")
-        .to_path_buf();
+/// Combine the single-track videos `tracks` into `output`, one video stream
+/// each in the given order, without re-encoding. The tracks are deleted on
+/// success.
+pub fn mux_tracks(tracks: &[PathBuf], output: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut command = Command::new(ffmpeg_path());
+    command.args(["-hide_banner", "-loglevel", "error", "-y"]);
+    for track in tracks {
+        command.arg("-i").arg(track);
+    }
+    for index in 0..tracks.len() {
+        command.args(["-map", &format!("{}:v", index)]);
+    }
+    let status = command.args(["-c", "copy"]).arg(output).status()?;
+    if !status.success() {
+        return Err(format!(
+            "FFmpeg failed to combine {} tracks: {}",
+            tracks.len(),
+            status
+        )
+        .into());
+    }
 
-    exe_dir.join("ffmpeg").join("ffmpeg.exe")
+    info!(path:% = output.display(), tracks = tracks.len(); "tracks combined");
+    for track in tracks {
+        fs::remove_file(track)?;
+    }
+    Ok(())
+}
+
+/// The ffmpeg bundled next to the executable (or in `C:\\ffmpeg` in debug
+/// builds) when present, otherwise `ffmpeg` from `PATH`.
+pub fn ffmpeg_path() -> PathBuf {
+    let name = if cfg!(windows) {
+        "ffmpeg.exe"
+    } else {
+        "ffmpeg"
+    };
+    let mut candidates = Vec::new();
+    if cfg!(all(windows, debug_assertions)) {
+        candidates.push(PathBuf::from("C:\\ffmpeg\\bin\\ffmpeg.exe"));
+    }
+    if let Some(exe_dir) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.to_path_buf()))
+    {
+        candidates.push(exe_dir.join("ffmpeg").join(name));
+    }
+    candidates
+        .into_iter()
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(name))
 }
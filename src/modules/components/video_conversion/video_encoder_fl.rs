@@ -0,0 +1,197 @@
+use log::{debug, info};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::thread::{self, JoinHandle};
+
+/// Number of ffmpeg stderr lines kept for error messages.
+const STDERR_TAIL_LINES: usize = 20;
+
+/// An ffmpeg process encoding raw BGRA frames written to its stdin, so a
+/// capture only ever occupies the compressed output on disk.
+///
+/// Writes block while ffmpeg is busy, which paces the capture to the
+/// encoder. Dropping the encoder without [`finish`](Self::finish) kills
+/// ffmpeg and leaves a truncated file behind.
+pub struct FrameEncoder {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stderr: Option<JoinHandle<Vec<String>>>,
+    output: PathBuf,
+    width: usize,
+    height: usize,
+    /// Tightly packed copy of the last frame, for [`repeat_frame`](Self::repeat_frame).
+    last: Vec<u8>,
+    frames: usize,
+}
+
+impl FrameEncoder {
+    /// Start `ffmpeg` writing H.264 into `output`, whose extension picks the
+    /// container, for `width`x`height` frames at a constant `fps`: every
+    /// frame written covers exactly `1 / fps` seconds of the video. An odd
+    /// width or height is padded with one black row or column, as yuv420p
+    /// needs even sides.
+    pub fn spawn(
+        ffmpeg: &Path,
+        output: &Path,
+        width: usize,
+        height: usize,
+        fps: u32,
+    ) -> io::Result<Self> {
+        debug!(ffmpeg:% = ffmpeg.display(), path:% = output.display(), width, height, fps; "starting encoder");
+        let mut child = Command::new(ffmpeg)
+            .args([
+                "-hide_banner",
+                "-loglevel",
+                "error",
+                "-y",
+                "-f",
+                "rawvideo",
+                "-pixel_format",
+                "bgra",
+                "-video_size",
+                &format!("{}x{}", width, height),
+                "-framerate",
+                &fps.max(1).to_string(),
+                "-i",
+                "pipe:0",
+                "-vf",
+                "pad=ceil(iw/2)*2:ceil(ih/2)*2",
+                "-c:v",
+                "libx264",
+                "-preset",
+                "ultrafast",
+                "-threads",
+                "2",
+                "-pix_fmt",
+                "yuv420p",
+            ])
+            .arg(output)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Drain stderr so ffmpeg never blocks on it, keeping the tail
+        let stderr = child.stderr.take().map(|stderr| {
+            thread::spawn(move || {
+                let mut tail = Vec::new();
+                for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                    if tail.len() == STDERR_TAIL_LINES {
+                        tail.remove(0);
+                    }
+                    tail.push(line);
+                }
+                tail
+            })
+        });
+
+        Ok(Self {
+            stdin: child.stdin.take(),
+            child,
+            stderr,
+            output: output.to_path_buf(),
+            width,
+            height,
+            last: Vec::new(),
+            frames: 0,
+        })
+    }
+
+    /// Frames written so far.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Send one BGRA frame. Rows may be padded beyond `width * 4` bytes, as
+    /// `scrap` does on some platforms; the padding is dropped.
+    pub fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        let row = self.width * 4;
+        let stride = frame.len() / self.height.max(1);
+        if stride < row {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "frame of {} bytes is too small for {}x{}",
+                    frame.len(),
+                    self.width,
+                    self.height
+                ),
+            ));
+        }
+        self.last.clear();
+        if stride == row {
+            self.last.extend_from_slice(&frame[..row * self.height]);
+        } else {
+            for line in frame.chunks_exact(stride).take(self.height) {
+                self.last.extend_from_slice(&line[..row]);
+            }
+        }
+        self.send_last()
+    }
+
+    /// Send the previous frame again, to hold it on screen for one more
+    /// frame interval. Before the first frame this sends a black one, so
+    /// every call still advances the video by one frame.
+    pub fn repeat_frame(&mut self) -> io::Result<()> {
+        if self.last.is_empty() {
+            self.last.resize(self.width * self.height * 4, 0);
+        }
+        self.send_last()
+    }
+
+    fn send_last(&mut self) -> io::Result<()> {
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or_else(|| io::Error::other("encoder is finished"))?;
+        match stdin.write_all(&self.last) {
+            Ok(()) => {
+                self.frames += 1;
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                // ffmpeg exited; its stderr says why
+                self.stdin = None;
+                let _ = self.child.wait();
+                Err(io::Error::other(format!(
+                    "ffmpeg exited early: {}",
+                    self.stderr_tail()
+                )))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Close ffmpeg's input and wait for it to finalize the file.
+    pub fn finish(mut self) -> io::Result<usize> {
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "ffmpeg {}: {}",
+                status,
+                self.stderr_tail()
+            )));
+        }
+        info!(path:% = self.output.display(), frames = self.frames; "encoding finished");
+        Ok(self.frames)
+    }
+
+    fn stderr_tail(&mut self) -> String {
+        self.stderr
+            .take()
+            .and_then(|handle| handle.join().ok())
+            .unwrap_or_default()
+            .join("; ")
+    }
+}
+
+impl Drop for FrameEncoder {
+    fn drop(&mut self) {
+        if self.stdin.take().is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
@@ -0,0 +1,542 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Child;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+/// Ask a process to exit on its own: `SIGTERM` on Unix, `CTRL_BREAK` on
+/// Windows. The latter only reaches a console child started with
+/// [`new_process_group`] from this process's console; otherwise an error is
+/// returned and callers go on to [`force_kill`].
+pub fn request_stop(pid: u32) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let pid = libc::pid_t::try_from(pid)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "pid out of range"))?;
+        // SAFETY: kill(2) has no memory-safety preconditions.
+        if unsafe { libc::kill(pid, libc::SIGTERM) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::Console::{GenerateConsoleCtrlEvent, CTRL_BREAK_EVENT};
+
+        // A process started with CREATE_NEW_PROCESS_GROUP leads a group with
+        // its own pid.
+        // SAFETY: GenerateConsoleCtrlEvent takes no pointers.
+        if unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid) } != 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+/// Start `command` in a process group of its own, so [`request_stop`] can
+/// send it `CTRL_BREAK` on Windows without reaching the agent. Does nothing
+/// on Unix, where `SIGTERM` goes to the process alone.
+pub fn new_process_group(command: &mut tokio::process::Command) -> &mut tokio::process::Command {
+    #[cfg(windows)]
+    command.creation_flags(windows_sys::Win32::System::Threading::CREATE_NEW_PROCESS_GROUP);
+    command
+}
+
+/// Kill a process outright: `SIGKILL` on Unix, `TerminateProcess` on
+/// Windows.
+pub fn force_kill(pid: u32) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let pid = libc::pid_t::try_from(pid)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "pid out of range"))?;
+        // SAFETY: kill(2) has no memory-safety preconditions.
+        if unsafe { libc::kill(pid, libc::SIGKILL) } == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        use windows_sys::Win32::System::Threading::{
+            OpenProcess, TerminateProcess, PROCESS_TERMINATE,
+        };
+
+        // SAFETY: the handle is checked for null and closed by `OwnedHandle`.
+        unsafe {
+            let handle = OwnedHandle(OpenProcess(PROCESS_TERMINATE, 0, pid));
+            if handle.0.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            if TerminateProcess(handle.0, 1) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A process told apart from any later one that reuses its PID by its start
+/// time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessIdentity {
+    pub pid: u32,
+    /// Start time in platform units: clock ticks since boot on Linux,
+    /// microseconds since the epoch on macOS, a `FILETIME` on Windows and
+    /// always 0 on other systems, where only the PID is compared.
+    pub started: u64,
+}
+
+impl ProcessIdentity {
+    /// Identity of the running process `pid`.
+    pub fn of(pid: u32) -> io::Result<Self> {
+        let started = start_time(pid)?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no process with pid {}", pid),
+            )
+        })?;
+        Ok(Self { pid, started })
+    }
+
+    /// Whether this process, and not just some process with its PID, is
+    /// still running.
+    pub fn is_running(&self) -> bool {
+        matches!(start_time(self.pid), Ok(Some(started)) if started == self.started)
+    }
+}
+
+/// Start time of `pid`, or `None` when no such process is running.
+#[cfg(target_os = "linux")]
+fn start_time(pid: u32) -> io::Result<Option<u64>> {
+    let stat = match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => stat,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    // The command name in parentheses may contain spaces; the fields after
+    // it start with the state (field 3), the start time is field 22
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed /proc stat");
+    let (_, fields) = stat.rsplit_once(')').ok_or_else(malformed)?;
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    if fields.first() == Some(&"Z") {
+        return Ok(None);
+    }
+    let started = fields.get(19).ok_or_else(malformed)?;
+    started.parse().map(Some).map_err(|_| malformed())
+}
+
+#[cfg(target_os = "macos")]
+fn start_time(pid: u32) -> io::Result<Option<u64>> {
+    let pid = libc::c_int::try_from(pid)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "pid out of range"))?;
+    let mut info = std::mem::MaybeUninit::<libc::proc_bsdinfo>::zeroed();
+    let size = std::mem::size_of::<libc::proc_bsdinfo>() as libc::c_int;
+    // SAFETY: `info` is a writable buffer of exactly `size` bytes.
+    let written = unsafe {
+        libc::proc_pidinfo(
+            pid,
+            libc::PROC_PIDTBSDINFO,
+            0,
+            info.as_mut_ptr().cast(),
+            size,
+        )
+    };
+    if written != size {
+        let error = io::Error::last_os_error();
+        return match error.raw_os_error() {
+            Some(libc::ESRCH) => Ok(None),
+            _ => Err(error),
+        };
+    }
+    // SAFETY: proc_pidinfo filled the whole struct.
+    let info = unsafe { info.assume_init() };
+    Ok(Some(
+        info.pbi_start_tvsec * 1_000_000 + info.pbi_start_tvusec,
+    ))
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "macos"))))]
+fn start_time(pid: u32) -> io::Result<Option<u64>> {
+    let pid = libc::pid_t::try_from(pid)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "pid out of range"))?;
+    // SAFETY: kill(2) with signal 0 only checks for the process.
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return Ok(Some(0));
+    }
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::ESRCH) => Ok(None),
+        Some(libc::EPERM) => Ok(Some(0)),
+        _ => Err(error),
+    }
+}
+
+#[cfg(windows)]
+fn start_time(pid: u32) -> io::Result<Option<u64>> {
+    use windows_sys::Win32::Foundation::{ERROR_INVALID_PARAMETER, FILETIME, WAIT_OBJECT_0};
+    use windows_sys::Win32::System::Threading::{
+        GetProcessTimes, OpenProcess, WaitForSingleObject, PROCESS_QUERY_LIMITED_INFORMATION,
+        PROCESS_SYNCHRONIZE,
+    };
+
+    let access = PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_SYNCHRONIZE;
+    // SAFETY: the handle is checked for null and closed by `OwnedHandle`;
+    // the FILETIMEs are plain out parameters.
+    unsafe {
+        let handle = OwnedHandle(OpenProcess(access, 0, pid));
+        if handle.0.is_null() {
+            let error = io::Error::last_os_error();
+            return match error.raw_os_error() {
+                Some(code) if code == ERROR_INVALID_PARAMETER as i32 => Ok(None),
+                _ => Err(error),
+            };
+        }
+        // Handles to exited processes stay valid while anyone holds one
+        if WaitForSingleObject(handle.0, 0) == WAIT_OBJECT_0 {
+            return Ok(None);
+        }
+        let zero = || FILETIME {
+            dwLowDateTime: 0,
+            dwHighDateTime: 0,
+        };
+        let (mut created, mut exited, mut kernel, mut user) = (zero(), zero(), zero(), zero());
+        if GetProcessTimes(handle.0, &mut created, &mut exited, &mut kernel, &mut user) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Some(
+            (u64::from(created.dwHighDateTime) << 32) | u64::from(created.dwLowDateTime),
+        ))
+    }
+}
+
+#[cfg(windows)]
+struct OwnedHandle(windows_sys::Win32::Foundation::HANDLE);
+
+#[cfg(windows)]
+impl Drop for OwnedHandle {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            // SAFETY: the handle came from OpenProcess and is closed once.
+            unsafe { windows_sys::Win32::Foundation::CloseHandle(self.0) };
+        }
+    }
+}
+
+/// Records of the processes an agent spawned, one `<pid>.json` per process,
+/// so `stop` finds exactly those and never an unrelated process that happens
+/// to have the same name.
+#[derive(Debug, Clone)]
+pub struct ProcessRegistry {
+    dir: PathBuf,
+}
+
+impl ProcessRegistry {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Record the just spawned process `pid`. The record is removed when the
+    /// returned guard is dropped.
+    pub fn track(&self, pid: u32) -> io::Result<Tracked> {
+        let identity = ProcessIdentity::of(pid)?;
+        fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("{}.json", pid));
+        fs::write(&path, serde_json::to_vec(&identity)?)?;
+        debug!(pid, path:% = path.display(); "tracking process");
+        Ok(Tracked { path, identity })
+    }
+
+    /// Recorded processes that are still running. Records of processes
+    /// that are gone, e.g. after a crash, are removed.
+    pub fn running(&self) -> io::Result<Vec<ProcessIdentity>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut running = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            match read_identity(&path) {
+                Some(identity) if identity.is_running() => running.push(identity),
+                _ => {
+                    debug!(path:% = path.display(); "removing stale process record");
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+        Ok(running)
+    }
+}
+
+fn read_identity(path: &Path) -> Option<ProcessIdentity> {
+    serde_json::from_slice(&fs::read(path).ok()?).ok()
+}
+
+/// A [`ProcessRegistry`] record, removed on drop.
+#[derive(Debug)]
+pub struct Tracked {
+    path: PathBuf,
+    identity: ProcessIdentity,
+}
+
+impl Tracked {
+    pub fn identity(&self) -> ProcessIdentity {
+        self.identity
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!(path:% = self.path.display(), error:% = e; "failed to remove process record");
+            }
+        }
+    }
+}
+
+/// Stop a process that is not our child: request an exit, wait up to
+/// `timeout`, then kill it. Returns whether it had to be killed.
+pub async fn stop_process(process: ProcessIdentity, timeout: Duration) -> io::Result<bool> {
+    let pid = process.pid;
+    if let Err(e) = request_stop(pid) {
+        warn!(pid, error:% = e; "failed to request process stop");
+    } else {
+        let deadline = tokio::time::Instant::now() + timeout;
+        while tokio::time::Instant::now() < deadline {
+            if !process.is_running() {
+                info!(pid; "process exited after stop request");
+                return Ok(false);
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+    if !process.is_running() {
+        return Ok(false);
+    }
+    warn!(pid; "process did not exit in time, killing");
+    force_kill(pid)?;
+    Ok(true)
+}
+
+/// Stop `child` gracefully: request an exit, wait up to `timeout`, then kill.
+pub async fn stop_child(child: &mut Child, timeout: Duration) {
+    if let Some(pid) = child.id() {
+        match request_stop(pid) {
+            Ok(()) => match tokio::time::timeout(timeout, child.wait()).await {
+                Ok(_) => {
+                    debug!(pid; "recorder exited after stop request");
+                    return;
+                }
+                Err(_) => {
+                    warn!(pid, timeout_ms = timeout.as_millis() as u64; "recorder ignored stop request, killing")
+                }
+            },
+            Err(e) => warn!(pid, error:% = e; "failed to request recorder stop, killing"),
+        }
+    }
+
+    if let Err(e) = child.kill().await {
+        warn!(error:% = e; "failed to kill recorder");
+    }
+}
+
+/// Stderr lines kept for error reports.
+const STDERR_TAIL_LINES: usize = 50;
+
+/// How long to keep reading output after the process exited, in case a
+/// grandchild still holds the pipes open.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Which pipe a line of output came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// How a supervised process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitKind {
+    /// Exited with status 0.
+    Success,
+    /// Exited with a non-zero code.
+    Failed(i32),
+    /// Terminated by a signal it did not handle (Unix).
+    Signaled(i32),
+    /// Stopped because the cancellation token fired.
+    Cancelled,
+    /// Killed by the watchdog after overrunning its deadline.
+    TimedOut,
+}
+
+impl ExitKind {
+    fn from_status(status: ExitStatus) -> Self {
+        if status.success() {
+            return ExitKind::Success;
+        }
+        if let Some(code) = status.code() {
+            return ExitKind::Failed(code);
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            if let Some(signal) = status.signal() {
+                return ExitKind::Signaled(signal);
+            }
+        }
+        ExitKind::Failed(-1)
+    }
+}
+
+impl fmt::Display for ExitKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExitKind::Success => f.write_str("exited successfully"),
+            ExitKind::Failed(code) => write!(f, "exited with code {}", code),
+            ExitKind::Signaled(signal) => write!(f, "was terminated by signal {}", signal),
+            ExitKind::Cancelled => f.write_str("was stopped on request"),
+            ExitKind::TimedOut => f.write_str("overran its deadline and was killed"),
+        }
+    }
+}
+
+/// Outcome of [`supervise`].
+#[derive(Debug)]
+pub struct Supervised {
+    pub exit: ExitKind,
+    /// The last lines the process wrote to stderr.
+    pub stderr_tail: String,
+}
+
+enum Wake {
+    Line(OutputStream, String),
+    Exited(io::Result<ExitStatus>),
+    Watchdog,
+    Cancel,
+}
+
+/// Run `child` to completion while reading its stdout and stderr
+/// concurrently, handing every line to `on_line`.
+///
+/// The child is killed once `deadline` has elapsed, and asked to stop (see
+/// [`stop_child`]) when `cancel` fires. Both pipes must have been set up with
+/// `Stdio::piped()`.
+pub async fn supervise(
+    mut child: Child,
+    deadline: Duration,
+    stop_timeout: Duration,
+    cancel: &CancellationToken,
+    mut on_line: impl FnMut(OutputStream, String),
+) -> io::Result<Supervised> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut readers = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        readers.push(spawn_reader(stdout, OutputStream::Stdout, tx.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        readers.push(spawn_reader(stderr, OutputStream::Stderr, tx.clone()));
+    }
+    drop(tx);
+
+    let mut stderr_tail = VecDeque::with_capacity(STDERR_TAIL_LINES);
+    let mut record = |stream: OutputStream, line: String| {
+        if stream == OutputStream::Stderr {
+            if stderr_tail.len() == STDERR_TAIL_LINES {
+                stderr_tail.pop_front();
+            }
+            stderr_tail.push_back(line.clone());
+        }
+        on_line(stream, line);
+    };
+
+    let watchdog = tokio::time::sleep(deadline);
+    tokio::pin!(watchdog);
+    let mut forced = None;
+
+    let status = loop {
+        let wake = tokio::select! {
+            Some((stream, line)) = rx.recv() => Wake::Line(stream, line),
+            status = child.wait() => Wake::Exited(status),
+            _ = &mut watchdog, if forced.is_none() => Wake::Watchdog,
+            _ = cancel.cancelled(), if forced.is_none() => Wake::Cancel,
+        };
+        match wake {
+            Wake::Line(stream, line) => record(stream, line),
+            Wake::Exited(status) => break status?,
+            Wake::Watchdog => {
+                forced = Some(ExitKind::TimedOut);
+                warn!(
+                    pid:? = child.id(),
+                    deadline_secs = deadline.as_secs();
+                    "process overran its deadline, killing"
+                );
+                if let Err(e) = child.start_kill() {
+                    warn!(error:% = e; "failed to kill process");
+                }
+            }
+            Wake::Cancel => {
+                forced = Some(ExitKind::Cancelled);
+                stop_child(&mut child, stop_timeout).await;
+            }
+        }
+    };
+
+    // Collect what is still buffered in the pipes
+    let drain = tokio::time::sleep(OUTPUT_DRAIN_TIMEOUT);
+    tokio::pin!(drain);
+    loop {
+        tokio::select! {
+            line = rx.recv() => match line {
+                Some((stream, line)) => record(stream, line),
+                None => break,
+            },
+            _ = &mut drain => {
+                debug!("output pipes still open after exit, giving up on them");
+                break;
+            }
+        }
+    }
+    for reader in readers {
+        reader.abort();
+    }
+
+    Ok(Supervised {
+        exit: forced.unwrap_or_else(|| ExitKind::from_status(status)),
+        stderr_tail: Vec::from(stderr_tail).join("\n"),
+    })
+}
+
+fn spawn_reader(
+    pipe: impl AsyncRead + Unpin + Send + 'static,
+    stream: OutputStream,
+    tx: mpsc::UnboundedSender<(OutputStream, String)>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(pipe).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if tx.send((stream, line)).is_err() {
+                break;
+            }
+        }
+    })
+}
@@ -0,0 +1,213 @@
+use std::env;
+use std::io;
+use std::net::IpAddr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hyper_util::rt::TokioIo;
+use log::debug;
+use percent_encoding::percent_decode_str;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tonic::transport::Uri;
+use url::Url;
+
+/// Where the proxy for a connection comes from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ProxyMode {
+    /// `HTTPS_PROXY`, `HTTP_PROXY` and `ALL_PROXY` (or their lowercase
+    /// forms), with `NO_PROXY` added to the configured exceptions.
+    #[default]
+    System,
+    /// Always connect directly.
+    None,
+    /// This `http://` proxy for everything.
+    Url(Url),
+}
+
+/// Proxy settings shared by the API client, the recorder download and the
+/// gRPC upload channel, so all three take the same route.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxyConfig {
+    pub mode: ProxyMode,
+    /// Hosts reached directly: `*`, a host name (also matching its
+    /// subdomains), `.domain` or an IP address.
+    pub no_proxy: Vec<String>,
+    /// Basic auth credentials; overrides any in the proxy URL.
+    pub auth: Option<(String, String)>,
+}
+
+impl ProxyConfig {
+    /// Direct connections only.
+    pub fn none() -> Self {
+        Self {
+            mode: ProxyMode::None,
+            ..Self::default()
+        }
+    }
+
+    /// The proxy to use for `scheme://host`, or `None` to connect directly.
+    pub fn proxy_for(&self, scheme: &str, host: &str) -> Option<Url> {
+        let proxy = match &self.mode {
+            ProxyMode::None => return None,
+            ProxyMode::Url(url) => url.clone(),
+            ProxyMode::System => system_proxy(scheme)?,
+        };
+        if self.bypasses(host) {
+            return None;
+        }
+        Some(proxy)
+    }
+
+    /// Basic auth for `proxy`, from the configuration or the proxy URL.
+    pub fn credentials(&self, proxy: &Url) -> Option<(String, String)> {
+        self.auth.clone().or_else(|| {
+            (!proxy.username().is_empty()).then(|| {
+                (
+                    decode(proxy.username()),
+                    decode(proxy.password().unwrap_or_default()),
+                )
+            })
+        })
+    }
+
+    /// Route a reqwest client through the configured proxy.
+    pub fn apply(&self, builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        // Replace reqwest's own environment lookup so every path agrees
+        let builder = builder.no_proxy();
+        if self.mode == ProxyMode::None {
+            return builder;
+        }
+        let config = self.clone();
+        let mut proxy = reqwest::Proxy::custom(move |url| {
+            config.proxy_for(url.scheme(), url.host_str().unwrap_or_default())
+        });
+        if let Some((username, password)) = &self.auth {
+            proxy = proxy.basic_auth(username, password);
+        }
+        builder.proxy(proxy)
+    }
+
+    fn bypasses(&self, host: &str) -> bool {
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_ascii_lowercase();
+        let from_env = match self.mode {
+            ProxyMode::System => env_var(&["NO_PROXY", "no_proxy"]).unwrap_or_default(),
+            _ => String::new(),
+        };
+        self.no_proxy
+            .iter()
+            .map(String::as_str)
+            .chain(from_env.split(','))
+            .map(|entry| entry.trim().to_ascii_lowercase())
+            .filter(|entry| !entry.is_empty())
+            .any(|entry| no_proxy_matches(&entry, &host))
+    }
+}
+
+fn no_proxy_matches(entry: &str, host: &str) -> bool {
+    if entry == "*" {
+        return true;
+    }
+    if let (Ok(entry), Ok(host)) = (entry.parse::<IpAddr>(), host.parse::<IpAddr>()) {
+        return entry == host;
+    }
+    let domain = entry.trim_start_matches("*.").trim_start_matches('.');
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+fn system_proxy(scheme: &str) -> Option<Url> {
+    let value = match scheme {
+        "https" => env_var(&["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]),
+        _ => env_var(&["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"]),
+    }?;
+    // Like curl, accept `host:port` without a scheme
+    let value = if value.contains("://") {
+        value
+    } else {
+        format!("http://{}", value)
+    };
+    Url::parse(&value).ok()
+}
+
+fn env_var(names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| env::var(name).ok())
+        .find(|value| !value.trim().is_empty())
+}
+
+fn decode(component: &str) -> String {
+    percent_decode_str(component)
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+/// Open a TCP connection to `target` through an HTTP `CONNECT` tunnel on
+/// `proxy`, for the gRPC channel. TLS to the target runs inside the tunnel.
+pub async fn connect_tunnel(
+    proxy: &Url,
+    credentials: Option<(String, String)>,
+    target: &Uri,
+) -> io::Result<TokioIo<TcpStream>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidInput, message.to_string());
+    let proxy_host = proxy
+        .host_str()
+        .ok_or_else(|| invalid("proxy URL has no host"))?;
+    let proxy_port = proxy.port_or_known_default().unwrap_or(8080);
+    let host = target
+        .host()
+        .ok_or_else(|| invalid("endpoint has no host"))?;
+    let port = target
+        .port_u16()
+        .unwrap_or(if target.scheme_str() == Some("https") {
+            443
+        } else {
+            80
+        });
+    let authority = if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+
+    debug!(proxy:% = proxy, target = authority.as_str(); "opening CONNECT tunnel");
+    let mut stream = TcpStream::connect((proxy_host, proxy_port)).await?;
+    stream.set_nodelay(true)?;
+
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if let Some((username, password)) = credentials {
+        let token = BASE64.encode(format!("{}:{}", username, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read the response head byte by byte so no tunnelled data is consumed
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "proxy closed the connection during CONNECT",
+            ));
+        }
+        head.push(byte[0]);
+        if head.len() > 16 * 1024 {
+            return Err(io::Error::other("proxy sent an oversized CONNECT response"));
+        }
+    }
+    let status_line = String::from_utf8_lossy(&head);
+    let status_line = status_line.lines().next().unwrap_or_default();
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+    if status != "200" {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("proxy refused CONNECT to {}: {}", authority, status_line),
+        ));
+    }
+    Ok(TokioIo::new(stream))
+}
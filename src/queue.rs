@@ -0,0 +1,535 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Container;
+
+/// Journal of finished segments, kept in the app directory.
+pub const JOURNAL_FILE: &str = "upload_queue.jsonl";
+
+/// Held exclusively, next to the journal, by the process that has the queue
+/// open. The journal itself cannot be locked as compaction replaces it.
+pub const LOCK_FILE: &str = "upload_queue.lock";
+
+/// Upper bound for the exponential backoff between attempts.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+/// Rewrite the journal once this many lines were appended since the last
+/// compaction.
+const COMPACT_AFTER: usize = 1000;
+
+/// Where a segment is in the upload pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadState {
+    /// Waiting for an upload attempt.
+    Pending,
+    /// Claimed by an upload worker.
+    Uploading,
+    /// Sent over gRPC; the API has not been told yet.
+    Uploaded,
+    /// Done. Kept while its local file still exists, so the file is not
+    /// adopted and uploaded again; dropped on the next compaction once the
+    /// file is gone.
+    Notified,
+    /// Cannot be uploaded (file missing or empty); kept until `queue retry`
+    /// or `queue purge`.
+    Failed,
+}
+
+impl UploadState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UploadState::Pending => "pending",
+            UploadState::Uploading => "uploading",
+            UploadState::Uploaded => "uploaded",
+            UploadState::Notified => "notified",
+            UploadState::Failed => "failed",
+        }
+    }
+}
+
+/// One journaled segment. Times are seconds since the Unix epoch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueEntry {
+    /// Segment id, see [`crate::run::segment_id`].
+    pub id: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub recorded_at: u64,
+    pub state: UploadState,
+    /// Failed attempts for the current state.
+    pub attempts: u32,
+    pub next_attempt_at: u64,
+    pub last_error: Option<String>,
+}
+
+/// Durable queue of segments waiting to be uploaded and announced to the API.
+///
+/// Every state change is appended to [`JOURNAL_FILE`] as a full JSON entry
+/// and flushed to disk; on [`open`](Self::open) the last line per segment
+/// wins, interrupted uploads go back to pending, untracked videos in the
+/// temp directory are adopted and the journal is compacted.
+///
+/// Only one process may have the queue open at a time, which [`LOCK_FILE`]
+/// enforces; the read-only [`read_entries`] is safe to use alongside a
+/// running agent.
+#[derive(Debug)]
+pub struct UploadQueue {
+    path: PathBuf,
+    tmp_dir: PathBuf,
+    /// Untracked videos modified more recently than this may still be
+    /// written by a recorder and are not adopted.
+    settle: Duration,
+    entries: BTreeMap<String, QueueEntry>,
+    /// Segments handed out by `claim_due` and not updated since.
+    claimed: HashSet<String>,
+    journal: File,
+    appended: usize,
+    /// Released when the queue is dropped.
+    _lock: File,
+}
+
+impl UploadQueue {
+    /// Open the queue in `app_dir`, failing with
+    /// [`WouldBlock`](io::ErrorKind::WouldBlock) when another process has it
+    /// open. Videos in `tmp_dir` left untouched for `settle`, normally the
+    /// segment duration, are adopted.
+    pub fn open(app_dir: &Path, tmp_dir: &Path, settle: Duration) -> io::Result<Self> {
+        let lock = lock_queue(app_dir)?;
+        let path = app_dir.join(JOURNAL_FILE);
+        let mut entries = load_journal(&path)?;
+
+        for entry in entries.values_mut() {
+            if entry.state == UploadState::Uploading {
+                debug!(segment = entry.id.as_str(); "resuming interrupted upload");
+                entry.state = UploadState::Pending;
+            }
+        }
+        let mut queue = Self {
+            journal: OpenOptions::new().create(true).append(true).open(&path)?,
+            path,
+            tmp_dir: tmp_dir.to_path_buf(),
+            settle,
+            entries,
+            claimed: HashSet::new(),
+            appended: 0,
+            _lock: lock,
+        };
+        queue.adopt_untracked()?;
+        queue.compact()?;
+        Ok(queue)
+    }
+
+    /// Track videos in the temp directory the journal does not know about,
+    /// such as segments kept on shutdown or recorded before a crash. Returns
+    /// how many were adopted.
+    pub fn adopt_untracked(&mut self) -> io::Result<usize> {
+        let now = unix_now();
+        let mut adopted = 0;
+        for (path, size, modified) in scan_videos(&self.tmp_dir)? {
+            let id = segment_key(&path);
+            if self.entries.contains_key(&id) {
+                continue;
+            }
+            if now.saturating_sub(modified) < self.settle.as_secs() {
+                debug!(segment = id.as_str(), path:% = path.display(); "not adopting recording that may still be written");
+                continue;
+            }
+            info!(segment = id.as_str(), path:% = path.display(); "adopting untracked recording");
+            self.save(QueueEntry::new(id, path, size, modified.min(now)))?;
+            adopted += 1;
+        }
+        Ok(adopted)
+    }
+
+    /// Every segment still waiting, oldest first.
+    pub fn entries(&self) -> Vec<&QueueEntry> {
+        let mut entries: Vec<_> = self
+            .entries
+            .values()
+            .filter(|e| e.state != UploadState::Notified)
+            .collect();
+        entries.sort_by_key(|e| e.recorded_at);
+        entries
+    }
+
+    /// Track a finished recording as pending. A segment that is already
+    /// tracked keeps its state; a pending one only has its size refreshed.
+    pub fn enqueue(&mut self, path: &Path) -> io::Result<()> {
+        let size = fs::metadata(path)?.len();
+        let id = segment_key(path);
+        match self.entries.get(&id) {
+            None => self.save(QueueEntry::new(id, path.to_path_buf(), size, unix_now())),
+            Some(existing) if existing.state == UploadState::Pending && existing.size != size => {
+                let entry = QueueEntry {
+                    size,
+                    ..existing.clone()
+                };
+                self.save(entry)
+            }
+            Some(existing) => {
+                warn!(
+                    segment = id.as_str(),
+                    state = existing.state.as_str();
+                    "segment is already queued, keeping its state"
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Claim the oldest segment whose next attempt is due. Pending segments
+    /// are marked uploading; uploaded ones only need the API notification.
+    pub fn claim_due(&mut self) -> io::Result<Option<QueueEntry>> {
+        let now = unix_now();
+        let due = self
+            .entries
+            .values()
+            .filter(|e| matches!(e.state, UploadState::Pending | UploadState::Uploaded))
+            .filter(|e| e.next_attempt_at <= now && !self.claimed.contains(&e.id))
+            .min_by_key(|e| (e.next_attempt_at, e.recorded_at))
+            .cloned();
+
+        let Some(mut entry) = due else {
+            return Ok(None);
+        };
+        if entry.state == UploadState::Pending {
+            entry.state = UploadState::Uploading;
+            self.save(entry.clone())?;
+        }
+        self.claimed.insert(entry.id.clone());
+        Ok(Some(entry))
+    }
+
+    /// Hand a claimed segment back without recording anything, after its
+    /// journal update failed. It is due again at once; an interrupted
+    /// upload starts over.
+    pub fn release(&mut self, id: &str) {
+        if self.claimed.remove(id) {
+            if let Some(entry) = self.entries.get_mut(id) {
+                if entry.state == UploadState::Uploading {
+                    entry.state = UploadState::Pending;
+                }
+            }
+        }
+    }
+
+    /// How long until the next segment becomes due, if any is waiting.
+    pub fn next_due_in(&self) -> Option<Duration> {
+        let now = unix_now();
+        self.entries
+            .values()
+            .filter(|e| matches!(e.state, UploadState::Pending | UploadState::Uploaded))
+            .filter(|e| !self.claimed.contains(&e.id))
+            .map(|e| Duration::from_secs(e.next_attempt_at.saturating_sub(now)))
+            .min()
+    }
+
+    pub fn mark_uploaded(&mut self, id: &str) -> io::Result<()> {
+        self.transition(id, UploadState::Uploaded)
+    }
+
+    pub fn mark_notified(&mut self, id: &str) -> io::Result<()> {
+        self.transition(id, UploadState::Notified)
+    }
+
+    /// Give up on a segment that cannot succeed by retrying.
+    pub fn mark_failed(&mut self, id: &str, error: String) -> io::Result<()> {
+        let Some(mut entry) = self.entries.get(id).cloned() else {
+            return Ok(());
+        };
+        entry.state = UploadState::Failed;
+        entry.last_error = Some(error);
+        self.save(entry)
+    }
+
+    /// Record a failed attempt and schedule the next one after an exponential
+    /// backoff starting at `base_delay`. Returns that delay.
+    pub fn retry_later(
+        &mut self,
+        id: &str,
+        error: String,
+        base_delay: Duration,
+    ) -> io::Result<Duration> {
+        let Some(mut entry) = self.entries.get(id).cloned() else {
+            return Ok(Duration::ZERO);
+        };
+        if entry.state == UploadState::Uploading {
+            entry.state = UploadState::Pending;
+        }
+        entry.attempts += 1;
+        let delay = backoff(base_delay, entry.attempts);
+        // Round up, so a sub-second delay still leaves a gap
+        entry.next_attempt_at = unix_now() + delay.as_millis().div_ceil(1000) as u64;
+        entry.last_error = Some(error);
+        self.save(entry)?;
+        Ok(delay)
+    }
+
+    /// Make failed segments pending again and every waiting segment due now.
+    /// Returns how many segments are waiting.
+    pub fn retry_all(&mut self) -> io::Result<usize> {
+        let waiting: Vec<_> = self
+            .entries
+            .values()
+            .filter(|e| e.state != UploadState::Notified)
+            .cloned()
+            .collect();
+        let count = waiting.len();
+        for mut entry in waiting {
+            if entry.state == UploadState::Failed {
+                entry.state = UploadState::Pending;
+            }
+            entry.attempts = 0;
+            entry.next_attempt_at = 0;
+            self.save(entry)?;
+        }
+        Ok(count)
+    }
+
+    /// Delete every recording still waiting, and any left by notified
+    /// segments, and forget about them. Returns how many waiting segments
+    /// were dropped.
+    pub fn purge(&mut self) -> io::Result<usize> {
+        let mut removed = 0;
+        for entry in self.entries.values() {
+            match fs::remove_file(&entry.path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+            if entry.state != UploadState::Notified {
+                removed += 1;
+            }
+        }
+        self.entries.clear();
+        self.claimed.clear();
+        self.compact()?;
+        Ok(removed)
+    }
+
+    fn transition(&mut self, id: &str, state: UploadState) -> io::Result<()> {
+        let Some(mut entry) = self.entries.get(id).cloned() else {
+            return Ok(());
+        };
+        entry.state = state;
+        entry.attempts = 0;
+        entry.next_attempt_at = 0;
+        entry.last_error = None;
+        self.save(entry)
+    }
+
+    fn save(&mut self, entry: QueueEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(&entry).map_err(io::Error::other)?;
+        line.push(b'\n');
+        self.journal.write_all(&line)?;
+        self.journal.sync_data()?;
+        self.appended += 1;
+        self.claimed.remove(&entry.id);
+        self.entries.insert(entry.id.clone(), entry);
+
+        if self.appended >= COMPACT_AFTER {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Drop notified segments whose file is gone, deleting it first where
+    /// that failed before, and rewrite the journal.
+    fn compact(&mut self) -> io::Result<()> {
+        self.entries.retain(|_, e| {
+            if e.state != UploadState::Notified {
+                return true;
+            }
+            match fs::remove_file(&e.path) {
+                Ok(()) => false,
+                Err(error) if error.kind() == io::ErrorKind::NotFound => false,
+                Err(error) => {
+                    warn!(
+                        segment = e.id.as_str(),
+                        path:% = e.path.display(),
+                        error:% = error;
+                        "notified recording still cannot be deleted"
+                    );
+                    true
+                }
+            }
+        });
+        write_journal(&self.path, self.entries.values())?;
+        self.journal = OpenOptions::new().append(true).open(&self.path)?;
+        self.appended = 0;
+        Ok(())
+    }
+}
+
+impl QueueEntry {
+    fn new(id: String, path: PathBuf, size: u64, recorded_at: u64) -> Self {
+        Self {
+            id,
+            path,
+            size,
+            recorded_at,
+            state: UploadState::Pending,
+            attempts: 0,
+            next_attempt_at: 0,
+            last_error: None,
+        }
+    }
+}
+
+/// Take [`LOCK_FILE`] in `app_dir` without waiting.
+fn lock_queue(app_dir: &Path) -> io::Result<File> {
+    let path = app_dir.join(LOCK_FILE);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            format!(
+                "the upload queue is in use by another screen_record process ({} is locked)",
+                path.display()
+            ),
+        )),
+        Err(TryLockError::Error(e)) => Err(e),
+    }
+}
+
+/// Journaled segments still waiting, without opening the queue, oldest
+/// first. Segments the agent has not adopted yet are not included.
+pub fn read_entries(app_dir: &Path) -> io::Result<Vec<QueueEntry>> {
+    let mut entries: Vec<_> = load_journal(&app_dir.join(JOURNAL_FILE))?
+        .into_values()
+        .filter(|e| e.state != UploadState::Notified)
+        .collect();
+    entries.sort_by_key(|e| e.recorded_at);
+    Ok(entries)
+}
+
+/// `base * 2^(attempt - 1)`, capped at [`MAX_BACKOFF`].
+pub fn backoff(base: Duration, attempt: u32) -> Duration {
+    let factor = 1u32
+        .checked_shl(attempt.saturating_sub(1))
+        .unwrap_or(u32::MAX);
+    base.saturating_mul(factor).min(MAX_BACKOFF)
+}
+
+fn load_journal(path: &Path) -> io::Result<BTreeMap<String, QueueEntry>> {
+    let mut entries = BTreeMap::new();
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(entries),
+        Err(e) => return Err(e),
+    };
+
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // A torn last line after a crash is expected; anything else is logged
+        match serde_json::from_str::<QueueEntry>(&line) {
+            Ok(entry) => {
+                entries.insert(entry.id.clone(), entry);
+            }
+            Err(e) => warn!(
+                path:% = path.display(),
+                line = number + 1,
+                error:% = e;
+                "skipping unreadable journal line"
+            ),
+        }
+    }
+    Ok(entries)
+}
+
+/// Atomically replace the journal with one line per entry.
+fn write_journal<'a>(path: &Path, entries: impl Iterator<Item = &'a QueueEntry>) -> io::Result<()> {
+    let tmp = path.with_extension("jsonl.tmp");
+    {
+        let mut file = File::create(&tmp)?;
+        for entry in entries {
+            let mut line = serde_json::to_vec(entry).map_err(io::Error::other)?;
+            line.push(b'\n');
+            file.write_all(&line)?;
+        }
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)
+}
+
+/// Video files in `tmp_dir` as `(path, size, modified)`.
+fn scan_videos(tmp_dir: &Path) -> io::Result<Vec<(PathBuf, u64, u64)>> {
+    let mut videos = Vec::new();
+
+    let entries = match fs::read_dir(tmp_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(videos),
+        Err(e) => return Err(e),
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_video = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.parse::<Container>().is_ok());
+        if !is_video || is_track_file(&path) {
+            continue;
+        }
+        if let Ok(metadata) = entry.metadata() {
+            if metadata.is_file() {
+                let modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |d| d.as_secs());
+                videos.push((path, metadata.len(), modified));
+            }
+        }
+    }
+    Ok(videos)
+}
+
+/// Whether `path` is one source's track, `<stem>.<source>.<ext>`, left by a
+/// recorder that never joined them into the segment. Segment stems end in
+/// the `%Y%m%dT%H%M%S` time they were started at.
+fn is_track_file(path: &Path) -> bool {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    stem.match_indices('.')
+        .any(|(dot, _)| ends_with_start_time(&stem[..dot]))
+}
+
+fn ends_with_start_time(stem: &str) -> bool {
+    let Some(time) = stem.as_bytes().last_chunk::<15>() else {
+        return false;
+    };
+    time.iter().enumerate().all(|(i, b)| match i {
+        8 => *b == b'T',
+        _ => b.is_ascii_digit(),
+    })
+}
+
+fn segment_key(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
@@ -0,0 +1,339 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use log::{info, warn};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{CaptureSourceKind, Container, RecorderBackend, RecordingConfig};
+use crate::error::{Result, ScreenRecordError};
+use crate::events::{EventSender, RecordingEvent};
+use crate::modules::components::record_screen::capture_source_fl::open_sources;
+use crate::modules::components::record_screen::record_screen_fl::{list_displays, record_screen};
+use crate::modules::components::video_conversion::video_conversion_fl::ffmpeg_path;
+use crate::run::{self, segment_id, AppDirs};
+use crate::updater;
+
+/// What a [`Recorder`] backend can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Containers it can write, the default one first.
+    pub containers: &'static [Container],
+    /// Whether it honours [`RecordingConfig::resolution`] rather than
+    /// capturing at the display's native size.
+    pub scales: bool,
+    /// Whether it needs the downloaded recorder executable.
+    pub downloads_recorder: bool,
+    /// Whether it can capture displays other than the primary one.
+    pub selects_display: bool,
+    /// Whether it can take frames from a synthetic
+    /// [`CaptureSourceKind`](crate::config::CaptureSourceKind).
+    pub synthetic_sources: bool,
+}
+
+impl RecorderBackend {
+    pub fn capabilities(&self) -> Capabilities {
+        match self {
+            RecorderBackend::External => Capabilities {
+                containers: &Container::ALL,
+                scales: true,
+                downloads_recorder: true,
+                selects_display: false,
+                synthetic_sources: false,
+            },
+            // Encoded with libx264, which WebM cannot hold
+            RecorderBackend::Native => Capabilities {
+                containers: &[Container::Mp4, Container::Mkv, Container::Avi],
+                scales: false,
+                downloads_recorder: false,
+                selects_display: true,
+                synthetic_sources: true,
+            },
+        }
+    }
+}
+
+/// The video file a finished segment produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentOutput {
+    pub path: PathBuf,
+    /// Cut short by a stop or cancellation rather than reaching the
+    /// configured segment duration.
+    pub cancelled: bool,
+}
+
+/// A segment being recorded in the background.
+///
+/// Dropping the handle without awaiting [`output`](Self::output) aborts the
+/// capture.
+pub struct RecordingHandle {
+    stop: CancellationToken,
+    task: Option<JoinHandle<Result<SegmentOutput>>>,
+}
+
+impl RecordingHandle {
+    fn spawn<F>(stop: CancellationToken, capture: F) -> Self
+    where
+        F: std::future::Future<Output = Result<SegmentOutput>> + Send + 'static,
+    {
+        Self {
+            stop,
+            task: Some(tokio::spawn(capture)),
+        }
+    }
+
+    /// Ask the recorder to finish the segment early. The partial recording
+    /// is still returned by [`output`](Self::output).
+    pub fn stop(&self) {
+        self.stop.cancel();
+    }
+
+    /// Wait for the segment to finish.
+    pub async fn output(mut self) -> Result<SegmentOutput> {
+        let task = self.task.take().expect("recording task already awaited");
+        match task.await {
+            Ok(result) => result,
+            Err(e) => Err(io::Error::other(format!("recording task failed: {}", e)).into()),
+        }
+    }
+}
+
+impl Drop for RecordingHandle {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            self.stop.cancel();
+            task.abort();
+        }
+    }
+}
+
+/// A way of capturing one segment into the temp directory.
+#[async_trait]
+pub trait Recorder: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn capabilities(&self) -> Capabilities;
+
+    /// Get ready and start capturing a segment of
+    /// [`RecordingConfig::segment_duration`]. Cancelling `cancel` stops the
+    /// segment early, like [`RecordingHandle::stop`]; when it is already
+    /// cancelled nothing is started and [`ScreenRecordError::Cancelled`] is
+    /// returned.
+    async fn start(
+        &self,
+        dirs: &AppDirs,
+        events: &EventSender,
+        cancel: &CancellationToken,
+    ) -> Result<RecordingHandle>;
+}
+
+/// The recorder backend selected by [`RecordingConfig::recorder_backend`].
+pub fn from_config(config: &RecordingConfig) -> Box<dyn Recorder> {
+    match config.recorder_backend() {
+        RecorderBackend::External => Box::new(ExternalExeRecorder::new(config.clone())),
+        RecorderBackend::Native => Box::new(NativeRecorder::new(config.clone())),
+    }
+}
+
+/// Runs the downloaded `screen_record.exe` for each segment, fetching or
+/// updating it first when needed.
+pub struct ExternalExeRecorder {
+    config: RecordingConfig,
+}
+
+impl ExternalExeRecorder {
+    pub fn new(config: RecordingConfig) -> Self {
+        Self { config }
+    }
+}
+
+#[async_trait]
+impl Recorder for ExternalExeRecorder {
+    fn name(&self) -> &'static str {
+        RecorderBackend::External.name()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        RecorderBackend::External.capabilities()
+    }
+
+    async fn start(
+        &self,
+        dirs: &AppDirs,
+        events: &EventSender,
+        cancel: &CancellationToken,
+    ) -> Result<RecordingHandle> {
+        let recorder_exe = match self.config.recorder_manifest_url() {
+            Some(url) => updater::active_recorder(&self.config, url, &dirs.bin_dir, events).await?,
+            None => run::ensure_recorder(&self.config, dirs, events).await?,
+        };
+        if cancel.is_cancelled() {
+            return Err(ScreenRecordError::Cancelled { kept: None });
+        }
+
+        let stop = cancel.child_token();
+        let (config, dirs, events, token) = (
+            self.config.clone(),
+            dirs.clone(),
+            events.clone(),
+            stop.clone(),
+        );
+        Ok(RecordingHandle::spawn(stop, async move {
+            let (path, cancelled) =
+                run::capture_segment(&config, &dirs, &recorder_exe, &events, &token).await?;
+            Ok(SegmentOutput { path, cancelled })
+        }))
+    }
+}
+
+/// Captures the configured displays in-process with `scrap`, streaming the
+/// frames into ffmpeg as they arrive. Needs no download, but always records
+/// at the displays' native resolution.
+pub struct NativeRecorder {
+    config: RecordingConfig,
+}
+
+impl NativeRecorder {
+    pub fn new(config: RecordingConfig) -> Self {
+        Self { config }
+    }
+
+    /// Check that `source` has frames to offer and ffmpeg can be found, for
+    /// `doctor`. Returns a description of the source.
+    pub fn probe(source: &CaptureSourceKind) -> std::result::Result<String, String> {
+        let detail = match source {
+            CaptureSourceKind::Display => {
+                let displays =
+                    list_displays().map_err(|e| format!("cannot list displays: {}", e))?;
+                if displays.is_empty() {
+                    return Err("no display found".into());
+                }
+                let displays: Vec<String> = displays
+                    .iter()
+                    .map(|d| format!("{} {}x{}", d.name, d.width, d.height))
+                    .collect();
+                format!("native capture, displays: {}", displays.join(", "))
+            }
+            CaptureSourceKind::TestPattern => "native capture of a test pattern".to_string(),
+            CaptureSourceKind::RawFile(path) => {
+                fs::metadata(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                format!("native capture replaying {}", path.display())
+            }
+        };
+        let ffmpeg = ffmpeg_path();
+        std::process::Command::new(&ffmpeg)
+            .arg("-version")
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .map_err(|e| format!("cannot run {}: {}", ffmpeg.display(), e))?;
+        Ok(detail)
+    }
+}
+
+#[async_trait]
+impl Recorder for NativeRecorder {
+    fn name(&self) -> &'static str {
+        RecorderBackend::Native.name()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        RecorderBackend::Native.capabilities()
+    }
+
+    async fn start(
+        &self,
+        dirs: &AppDirs,
+        events: &EventSender,
+        cancel: &CancellationToken,
+    ) -> Result<RecordingHandle> {
+        if cancel.is_cancelled() {
+            return Err(ScreenRecordError::Cancelled { kept: None });
+        }
+        if !dirs.tmp_dir.exists() {
+            warn!(path:% = dirs.tmp_dir.display(); "temp directory disappeared, recreating");
+            fs::create_dir_all(&dirs.tmp_dir)?;
+        }
+
+        let path = run::segment_path(&self.config, &dirs.tmp_dir);
+        let segment = segment_id(&path).to_string();
+        let duration = self.config.segment_duration();
+        let fps = self.config.fps().get();
+        let display = self.config.display().clone();
+        let layout = self.config.display_layout();
+        let source = self.config.capture_source().clone();
+        let resolution = self.config.resolution();
+
+        info!(
+            segment = segment.as_str(),
+            path:% = path.display(),
+            fps,
+            source:% = source,
+            display:% = display,
+            layout:% = layout;
+            "starting native capture"
+        );
+        events.emit(RecordingEvent::RecordingStarted {
+            segment,
+            path: path.clone(),
+            duration,
+        });
+
+        let stop = cancel.child_token();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let token = stop.clone();
+        let flag = Arc::clone(&stopped);
+        // Capture runs on a blocking thread that polls `stopped` between frames
+        let watcher = tokio::spawn(async move {
+            token.cancelled().await;
+            flag.store(true, Ordering::Relaxed);
+        });
+
+        Ok(RecordingHandle::spawn(stop, async move {
+            let capture = Arc::clone(&stopped);
+            let result = tokio::task::spawn_blocking(move || {
+                let captured = match open_sources(&source, &display, resolution)
+                    .map_err(Into::into)
+                    .and_then(|sources| {
+                        record_screen(&path, sources, layout, duration, fps, &capture)
+                    }) {
+                    Ok(stats) if stats.frames == 0 => Err("no frames were captured".to_string()),
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(format!("screen capture failed: {}", e)),
+                };
+                if captured.is_err() {
+                    remove_partial(&path);
+                }
+                captured.map(|()| path)
+            })
+            .await;
+            watcher.abort();
+
+            let cancelled = stopped.load(Ordering::Relaxed);
+            match result {
+                Ok(Ok(path)) => Ok(SegmentOutput { path, cancelled }),
+                Ok(Err(_)) if cancelled => Err(ScreenRecordError::Cancelled { kept: None }),
+                Ok(Err(reason)) => Err(ScreenRecordError::NativeCapture { reason }),
+                Err(e) => Err(ScreenRecordError::NativeCapture {
+                    reason: format!("capture thread failed: {}", e),
+                }),
+            }
+        }))
+    }
+}
+
+/// Delete a failed segment and any per-source tracks left next to it.
+fn remove_partial(path: &Path) {
+    let _ = fs::remove_file(path);
+    let tracks = format!("{}.", segment_id(path));
+    let Some(dir) = path.parent() else { return };
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        if entry.file_name().to_string_lossy().starts_with(&tracks) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
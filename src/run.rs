@@ -1,30 +1,47 @@
-use chrono::Utc;
-use grpc_video_server::file_upload_to_grpc;
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
 use reqwest::Client;
 use std::fs;
 use std::io;
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use std::time::Duration;
 use std::time::Instant;
-
+use tokio::sync::Notify;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{ConfigError, Container, RecordingConfig};
+use crate::error::{Result, ScreenRecordError};
+use crate::events::{EventSender, RecordingEvent};
+use crate::integrity::{self, IntegrityError, Sha256Digest};
+use crate::modules::api::download::{download_client, download_recorder_exe, RetryPolicy};
+use crate::modules::api::grpc_upload::{UploadOptions, Uploader};
 use crate::modules::api::upload_video_id_fl::video_id_send_to_api_fn;
+use crate::process::{
+    new_process_group, stop_process, supervise, ExitKind, OutputStream, ProcessRegistry,
+};
+use crate::queue::{QueueEntry, UploadQueue, UploadState};
+use crate::recorder;
+use crate::updater;
 
 pub const VIDEO_RECORDER_EXE: &str = "screen_record.exe";
 
+/// A recorder download smaller than this is treated as truncated.
+pub const MIN_RECORDER_SIZE: u64 = 50_000_000;
+
 /// Result of app directory lookup with diagnostic info
-struct AppDirectoryResult {
-    app_dir: PathBuf,
-    source: String,
-    checked_paths: Vec<(String, PathBuf, bool)>, // (source_name, path, exists)
+#[derive(Debug, Clone)]
+pub struct AppDirectoryResult {
+    pub app_dir: PathBuf,
+    pub source: String,
+    pub checked_paths: Vec<(String, PathBuf, bool)>, // (source_name, path, exists)
 }
 
 /// Get a stable application directory that works when used as a library.
 /// This ensures the downloaded exe is found regardless of which project uses this library.
-fn get_app_directory_with_info() -> Result<AppDirectoryResult, Box<dyn std::error::Error>> {
+pub fn get_app_directory_with_info() -> Result<AppDirectoryResult> {
     let mut checked_paths = Vec::new();
 
     // Try LOCALAPPDATA (Windows) first
@@ -60,7 +77,11 @@ fn get_app_directory_with_info() -> Result<AppDirectoryResult, Box<dyn std::erro
     }
 
     // Fallback to exe directory
-    let exe_dir = std::env::current_exe()?.parent().unwrap().to_path_buf();
+    let exe_dir = std::env::current_exe()
+        .map_err(ScreenRecordError::AppDir)?
+        .parent()
+        .unwrap()
+        .to_path_buf();
     let exe_path = exe_dir.join("bin").join("screen_record.exe");
     let exists = exe_path.exists();
     checked_paths.push(("EXE_DIR".to_string(), exe_path, exists));
@@ -72,256 +93,731 @@ fn get_app_directory_with_info() -> Result<AppDirectoryResult, Box<dyn std::erro
     })
 }
 
-lazy_static::lazy_static! {
-    static ref VIDEO_BUFFER: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+/// The app directory together with its `temp` and `bin` subdirectories.
+#[derive(Debug, Clone)]
+pub struct AppDirs {
+    pub info: AppDirectoryResult,
+    pub app_dir: PathBuf,
+    pub tmp_dir: PathBuf,
+    pub bin_dir: PathBuf,
+    /// Records of the running recorder processes.
+    pub run_dir: PathBuf,
 }
-// screen_record.exe
-pub async fn process_screen_recording(
-    user_id: &str,
-    api_url: &str,
-    recorder_exe_url: &str,
-    grpc_server_ip: &str,
-    grpc_server_port: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let now = Utc::now();
-    let ts = now.format("%Y%m%dT%H%M%S").to_string();
 
+impl AppDirs {
+    /// Locate the app directory without creating `temp` or `bin`.
+    pub fn locate() -> Result<Self> {
+        let info = get_app_directory_with_info()?;
+        let app_dir = info.app_dir.clone();
+        Ok(Self {
+            tmp_dir: app_dir.join("temp"),
+            bin_dir: app_dir.join("bin"),
+            run_dir: app_dir.join("run"),
+            app_dir,
+            info,
+        })
+    }
+
+    pub fn recorder_exe(&self) -> PathBuf {
+        self.bin_dir.join(VIDEO_RECORDER_EXE)
+    }
+
+    /// The recorder processes started from this app directory.
+    pub fn recorders(&self) -> ProcessRegistry {
+        ProcessRegistry::new(&self.run_dir)
+    }
+}
+
+/// Locate the app directory and make sure its temp directory is usable.
+pub fn prepare_app_dirs() -> Result<AppDirs> {
     // Use a stable directory that works when used as a library
     // Priority: LOCALAPPDATA/screen_record > HOME/.screen_record > exe_dir
-    let app_dir_info = get_app_directory_with_info()?;
-    let app_dir = app_dir_info.app_dir.clone();
-    let tmp_dir = app_dir.join("temp");
-    let bin_dir = app_dir.join("bin");
+    let dirs = AppDirs::locate()?;
 
-    println!("📁 Using app directory: {} (source: {})", app_dir.display(), app_dir_info.source);
+    info!(
+        app_dir:% = dirs.app_dir.display(),
+        source = dirs.info.source.as_str();
+        "using app directory"
+    );
 
     // Ensure temp directory exists and is writable
-    println!("📂 Ensuring temp directory exists: {}", tmp_dir.display());
-    fs::create_dir_all(&tmp_dir).map_err(|e| {
-        format!("Failed to create temp directory at '{}': {}", tmp_dir.display(), e)
-    })?;
+    debug!(path:% = dirs.tmp_dir.display(); "ensuring temp directory exists");
+    let temp_dir_error = |source| ScreenRecordError::TempDir {
+        path: dirs.tmp_dir.clone(),
+        source,
+    };
+    fs::create_dir_all(&dirs.tmp_dir).map_err(temp_dir_error)?;
 
     // Verify directory is accessible
-    match fs::read_dir(&tmp_dir) {
-        Ok(_) => println!("✅ Temp directory is accessible"),
-        Err(e) => {
-            return Err(format!(
-                "Temp directory exists but is not accessible: {} - Error: {}",
-                tmp_dir.display(),
-                e
-            ).into());
-        }
+    fs::read_dir(&dirs.tmp_dir).map_err(temp_dir_error)?;
+    debug!(path:% = dirs.tmp_dir.display(); "temp directory is accessible");
+
+    Ok(dirs)
+}
+
+/// How often idle upload workers look at the queue without being woken.
+const QUEUE_IDLE_RECHECK: Duration = Duration::from_secs(60);
+
+/// A recording agent bound to one validated [`RecordingConfig`].
+///
+/// Cloning is cheap and shares the HTTP client and event channel.
+#[derive(Clone)]
+pub struct RecordingSession {
+    config: RecordingConfig,
+    client: Client,
+    uploader: Uploader,
+    events: EventSender,
+}
+
+impl RecordingSession {
+    /// Must be called from within a Tokio runtime, which drives the upload
+    /// channel.
+    pub fn new(config: RecordingConfig) -> Result<Self> {
+        let client = config
+            .api_tls()
+            .apply(config.proxy().apply(Client::builder()), "api")
+            .connect_timeout(Duration::from_secs(15))
+            .timeout(Duration::from_secs(60))
+            .build()
+            .map_err(ScreenRecordError::HttpClient)?;
+
+        let uploader = Uploader::new(
+            config.grpc_endpoint().clone(),
+            UploadOptions {
+                chunk_size: config.upload_chunk_size(),
+                connect_timeout: config.connect_timeout(),
+                request_timeout: config.upload_timeout(),
+                keepalive: config.keepalive(),
+                max_message_size: config.max_message_size(),
+                tls: config.grpc_tls().cloned(),
+                proxy: config.proxy().clone(),
+            },
+        )
+        .map_err(ScreenRecordError::UploadChannel)?;
+
+        Ok(Self {
+            config,
+            client,
+            uploader,
+            events: EventSender::new(),
+        })
     }
 
-    // Download recorder executable from URL
-    let recorder_exe_path = bin_dir.join("screen_record.exe");
+    pub fn config(&self) -> &RecordingConfig {
+        &self.config
+    }
 
-    // Check if the file already exists with correct size
-    let needs_download = if recorder_exe_path.exists() {
-        if let Ok(metadata) = fs::metadata(&recorder_exe_path) {
-            let size = metadata.len();
-            println!("📦 Found existing recorder executable ({} MB)", size / 1_000_000);
-            // If file is too small, re-download
-            if size < 50_000_000 {
-                println!("⚠️ File size is too small, will re-download");
-                true
-            } else {
-                println!("✅ Using existing recorder executable");
-                false
+    /// Receive [`RecordingEvent`]s for every cycle run on this session.
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<RecordingEvent> {
+        self.events.subscribe()
+    }
+
+    /// Record one segment, upload it and notify the API.
+    pub async fn record_segment(&self) -> Result<()> {
+        self.record_segment_until(&CancellationToken::new()).await
+    }
+
+    /// Like [`record_segment`](Self::record_segment), but stops the recorder
+    /// early once `cancel` fires. The partial recording is then uploaded when
+    /// [`RecordingConfig::upload_partial`] is set, or kept in the temp
+    /// directory and reported as [`ScreenRecordError::Cancelled`].
+    pub async fn record_segment_until(&self, cancel: &CancellationToken) -> Result<()> {
+        let final_path = self.record_until(cancel).await?;
+        self.upload_file(&final_path).await?;
+
+        info!(segment = segment_id(&final_path); "segment processed");
+        Ok(())
+    }
+
+    /// Record segments back to back until `cancel` fires. Every finished file
+    /// is journaled in the [`UploadQueue`] and uploaded by a pool of
+    /// [`RecordingConfig::upload_workers`] tasks, so the next segment starts
+    /// while the previous one uploads and nothing is lost across restarts.
+    ///
+    /// Recording failures are logged and retried after
+    /// [`RecordingConfig::retry_delay`]. On cancellation the workers finish
+    /// their current upload; everything else stays queued for the next run.
+    pub async fn run_continuous(&self, cancel: &CancellationToken) -> Result<()> {
+        let dirs = prepare_app_dirs()?;
+        let queue = Arc::new(Mutex::new(UploadQueue::open(
+            &dirs.app_dir,
+            &dirs.tmp_dir,
+            self.config.segment_duration(),
+        )?));
+        let wake = Arc::new(Notify::new());
+
+        let workers = self.config.upload_workers().get();
+        let mut pool = JoinSet::new();
+        for worker in 0..workers {
+            let session = self.clone();
+            let queue = Arc::clone(&queue);
+            let wake = Arc::clone(&wake);
+            let cancel = cancel.clone();
+            pool.spawn(async move { session.upload_worker(worker, &queue, &wake, &cancel).await });
+        }
+        if let Some(url) = self.config.recorder_manifest_url() {
+            pool.spawn(updater::run_updates(
+                self.config.clone(),
+                url.clone(),
+                dirs.bin_dir.clone(),
+                self.events.clone(),
+                cancel.clone(),
+            ));
+        }
+
+        while !cancel.is_cancelled() {
+            match self.record_until(cancel).await {
+                Ok(path) => {
+                    if let Err(e) = lock(&queue).enqueue(&path) {
+                        error!(
+                            segment = segment_id(&path),
+                            error:% = e;
+                            "failed to journal segment, it will be picked up on the next start"
+                        );
+                    }
+                    wake.notify_one();
+                }
+                Err(ScreenRecordError::Cancelled { kept }) => {
+                    if let Some(path) = kept {
+                        info!(path:% = path.display(); "partial segment kept for a later upload");
+                    }
+                    break;
+                }
+                Err(e) => {
+                    error!(error:% = e, hint = e.hint().unwrap_or_default(); "recording failed");
+                    tokio::select! {
+                        _ = tokio::time::sleep(self.config.retry_delay()) => {}
+                        _ = cancel.cancelled() => {}
+                    }
+                }
             }
-        } else {
-            true
         }
-    } else {
-        true
-    };
 
-    if needs_download {
-        println!("📥 Downloading recorder executable from: {}", recorder_exe_url);
+        info!(workers; "waiting for upload workers to finish");
+        while pool.join_next().await.is_some() {}
+        Ok(())
+    }
 
-        // Ensure bin directory exists
-        fs::create_dir_all(&bin_dir).map_err(|e| {
-            format!("Failed to create bin directory at '{}': {}", bin_dir.display(), e)
+    /// Check the recorder manifest now and switch to its version. Returns
+    /// the newly active version, or `None` when it already was active.
+    pub async fn update_recorder(&self) -> Result<Option<semver::Version>> {
+        let url = self.config.recorder_manifest_url().ok_or_else(|| {
+            ScreenRecordError::RecorderUpdate {
+                url: String::new(),
+                reason: "recorder_manifest_url is not configured".into(),
+            }
         })?;
+        let dirs = prepare_app_dirs()?;
+        updater::update(&self.config, url, &dirs.bin_dir, &self.events).await
+    }
 
-        // Use std::thread to avoid Tokio runtime conflicts entirely
-        let url = recorder_exe_url.to_string();
-        let exe_path = recorder_exe_path.clone();
+    /// Make every queued segment due now, including failed ones, and work
+    /// through the queue once. Returns how many segments are still queued.
+    pub async fn retry_queue(&self) -> Result<usize> {
+        let dirs = prepare_app_dirs()?;
+        let queue = Mutex::new(UploadQueue::open(
+            &dirs.app_dir,
+            &dirs.tmp_dir,
+            self.config.segment_duration(),
+        )?);
+        let waiting = lock(&queue).retry_all()?;
+        info!(waiting; "retrying queued segments");
+
+        loop {
+            let claimed = lock(&queue).claim_due()?;
+            let Some(entry) = claimed else { break };
+            if let Err(e) = self.process_entry(&queue, &entry).await {
+                lock(&queue).release(&entry.id);
+                return Err(e.into());
+            }
+        }
 
-        let download_thread = std::thread::spawn(move || -> Result<(), String> {
-            println!("🔗 Attempting to download from: {}", url);
+        let remaining = lock(&queue).entries().len();
+        Ok(remaining)
+    }
 
-            // First, try with reqwest
-            println!("📡 Method 1: Trying reqwest HTTP client...");
-            match try_download_with_reqwest(&url, &exe_path) {
-                Ok(_) => {
-                    println!("✅ Download successful with reqwest");
-                    return Ok(());
+    async fn upload_worker(
+        &self,
+        worker: u32,
+        queue: &Mutex<UploadQueue>,
+        wake: &Notify,
+        cancel: &CancellationToken,
+    ) {
+        while !cancel.is_cancelled() {
+            let claimed = lock(queue).claim_due();
+            let result = match claimed {
+                Ok(Some(entry)) => {
+                    debug!(
+                        worker,
+                        segment = entry.id.as_str(),
+                        state = entry.state.as_str();
+                        "upload worker picked up segment"
+                    );
+                    let result = self.process_entry(queue, &entry).await;
+                    if result.is_err() {
+                        lock(queue).release(&entry.id);
+                    }
+                    result
                 }
-                Err(e) => {
-                    println!("⚠️  Reqwest download failed: {}", e);
-                    println!("🔄 Trying alternative download method...");
+                Ok(None) => {
+                    // Segments kept by an earlier run become adoptable once settled
+                    match lock(queue).adopt_untracked() {
+                        Ok(0) => {}
+                        Ok(_) => continue,
+                        Err(e) => warn!(worker, error:% = e; "failed to scan the temp directory"),
+                    }
+                    let idle = lock(queue).next_due_in().unwrap_or(QUEUE_IDLE_RECHECK);
+                    tokio::select! {
+                        _ = wake.notified() => {}
+                        _ = tokio::time::sleep(idle) => {}
+                        _ = cancel.cancelled() => {}
+                    }
+                    continue;
+                }
+                Err(e) => Err(e),
+            };
+
+            if let Err(e) = result {
+                error!(worker, error:% = e; "failed to update the upload queue journal");
+                tokio::select! {
+                    _ = tokio::time::sleep(self.config.retry_delay()) => {}
+                    _ = cancel.cancelled() => {}
                 }
             }
+        }
+    }
 
-            // Fallback: Try with curl
-            println!("📡 Method 2: Trying curl command...");
-            match try_download_with_curl(&url, &exe_path) {
+    /// Move a claimed queue entry forward: upload it if it is pending, then
+    /// tell the API about it. Failures are scheduled for a later attempt.
+    async fn process_entry(
+        &self,
+        queue: &Mutex<UploadQueue>,
+        entry: &QueueEntry,
+    ) -> io::Result<()> {
+        let segment = entry.id.as_str();
+
+        if entry.state == UploadState::Uploading {
+            match fs::metadata(&entry.path) {
+                Ok(metadata) if metadata.len() > 0 => {}
                 Ok(_) => {
-                    println!("✅ Download successful with curl");
-                    return Ok(());
+                    warn!(segment, path:% = entry.path.display(); "queued recording is empty");
+                    return lock(queue).mark_failed(segment, "recording is empty".to_string());
                 }
                 Err(e) => {
-                    println!("⚠️  Curl download failed: {}", e);
-                    println!("🔄 Trying final method...");
+                    warn!(segment, path:% = entry.path.display(), error:% = e; "queued recording is unreadable");
+                    return lock(queue).mark_failed(segment, e.to_string());
                 }
             }
 
-            // Fallback: Try with PowerShell (Windows)
-            if cfg!(windows) {
-                println!("📡 Method 3: Trying PowerShell (Windows native)...");
-                match try_download_with_powershell(&url, &exe_path) {
-                    Ok(_) => {
-                        println!("✅ Download successful with PowerShell");
-                        return Ok(());
-                    }
-                    Err(e) => {
-                        println!("⚠️  PowerShell download failed: {}", e);
-                    }
-                }
+            let attempt = entry.attempts + 1;
+            if let Err(e) = self.upload_once(&entry.path, attempt).await {
+                let delay =
+                    lock(queue).retry_later(segment, e.clone(), self.config.retry_delay())?;
+                warn!(
+                    segment,
+                    attempt,
+                    retry_in_ms = delay.as_millis() as u64,
+                    error:% = e;
+                    "upload failed, will retry"
+                );
+                self.events.emit(RecordingEvent::UploadRetry {
+                    segment: segment.to_string(),
+                    attempt,
+                    delay,
+                    error: e,
+                });
+                return Ok(());
+            }
+            lock(queue).mark_uploaded(segment)?;
+        }
+
+        match self
+            .notify_api(&entry.path, unix_time(entry.recorded_at))
+            .await
+        {
+            Ok(()) => {
+                // Journaled first: if the delete fails, the notified entry
+                // keeps the file from being adopted and uploaded again
+                lock(queue).mark_notified(segment)?;
+                self.remove_local_copy(&entry.path);
+                info!(segment; "segment processed");
+            }
+            Err(e) => {
+                let delay =
+                    lock(queue).retry_later(segment, e.to_string(), self.config.retry_delay())?;
+                warn!(
+                    segment,
+                    retry_in_ms = delay.as_millis() as u64;
+                    "API notification will be retried"
+                );
             }
+        }
+        Ok(())
+    }
 
-            Err(format!(
-                "All download methods failed for URL: {}\n\
-                Please ensure:\n\
-                  1. The URL is accessible from your network\n\
-                  2. The server at {} is running\n\
-                  3. Firewall/proxy settings allow the connection\n\
-                  4. Try manually downloading from the URL in a browser",
-                url, url
-            ))
+    /// Record one segment and return the file to upload. A segment cut short
+    /// by `cancel` is returned only when [`RecordingConfig::upload_partial`]
+    /// is set.
+    async fn record_until(&self, cancel: &CancellationToken) -> Result<PathBuf> {
+        let dirs = prepare_app_dirs()?;
+        let recorder = recorder::from_config(&self.config);
+        debug!(backend = recorder.name(); "starting segment");
+        let recording = recorder.start(&dirs, &self.events, cancel).await?;
+        let output = recording.output().await?;
+        let (final_path, cancelled) = (output.path, output.cancelled);
+
+        let size = fs::metadata(&final_path)?.len();
+        info!(
+            segment = segment_id(&final_path),
+            bytes = size,
+            partial = cancelled;
+            "recording completed"
+        );
+        self.events.emit(RecordingEvent::RecordingFinished {
+            segment: segment_id(&final_path).to_string(),
+            path: final_path.clone(),
+            size,
         });
 
-        fn try_download_with_reqwest(url: &str, exe_path: &std::path::Path) -> Result<(), String> {
-            let client = reqwest::blocking::Client::builder()
-                .danger_accept_invalid_certs(true)
-                .connect_timeout(Duration::from_secs(30))
-                .timeout(Duration::from_secs(500))
-                .tcp_keepalive(Duration::from_secs(20))
-                .pool_idle_timeout(Duration::from_secs(90))
-                .user_agent("screen_record/0.1.1")
-                .no_proxy()  // Disable proxy to avoid issues
-                .build()
-                .map_err(|e| format!("Failed to build HTTP client: {}", e))?;
-
-            println!("   Connecting to {}...", url);
-            let response = client
-                .get(url)
-                .send()
-                .map_err(|e| {
-                    let mut error_details = format!("Connection error: {}", e);
-                    if e.is_timeout() {
-                        error_details.push_str(" (timeout)");
-                    } else if e.is_connect() {
-                        error_details.push_str(" (connection refused/failed)");
-                    } else if e.is_request() {
-                        error_details.push_str(" (request failed)");
-                    }
-                    error_details
-                })?;
+        if cancelled && !self.config.upload_partial() {
+            info!(
+                segment = segment_id(&final_path),
+                path:% = final_path.display();
+                "keeping partial recording for a later upload"
+            );
+            return Err(ScreenRecordError::Cancelled {
+                kept: Some(final_path),
+            });
+        }
+        Ok(final_path)
+    }
 
-            println!("   Response: HTTP {}", response.status());
+    /// Upload an existing recording over gRPC, notify the API and delete the file.
+    ///
+    /// Upload attempts are retried right away, up to
+    /// [`RecordingConfig::upload_attempts`]; a failed API notification is only
+    /// logged. Use [`run_continuous`](Self::run_continuous) for durable retries.
+    pub async fn upload_file(&self, final_path: &Path) -> Result<()> {
+        let file_size = fs::metadata(final_path)?.len();
+
+        if file_size == 0 {
+            return Err(ScreenRecordError::EmptyOutput {
+                path: final_path.to_path_buf(),
+            });
+        }
 
-            if !response.status().is_success() {
-                return Err(format!("HTTP error: {}", response.status()));
+        let segment = segment_id(final_path);
+        let max_retries = self.config.upload_attempts().get();
+        let mut attempt = 0;
+
+        loop {
+            attempt += 1;
+            match self.upload_once(final_path, attempt).await {
+                Ok(()) => break,
+                Err(e) if attempt < max_retries => {
+                    warn!(
+                        segment,
+                        attempt,
+                        max_attempts = max_retries,
+                        retry_in_ms = self.config.retry_delay().as_millis() as u64,
+                        error:% = e;
+                        "upload failed, retrying"
+                    );
+                    self.events.emit(RecordingEvent::UploadRetry {
+                        segment: segment.to_string(),
+                        attempt,
+                        delay: self.config.retry_delay(),
+                        error: e,
+                    });
+                    tokio::time::sleep(self.config.retry_delay()).await;
+                }
+                Err(e) => {
+                    error!(segment, attempts = attempt, error:% = e; "all upload attempts failed");
+                    return Err(ScreenRecordError::Upload {
+                        path: final_path.to_path_buf(),
+                        attempts: attempt,
+                        last_error: e,
+                    });
+                }
             }
+        }
 
-            let bytes = response.bytes()
-                .map_err(|e| format!("Failed to read bytes: {}", e))?;
+        // Don't fail the entire process for API issues
+        let recorded_at = fs::metadata(final_path)
+            .and_then(|metadata| metadata.modified())
+            .map_or_else(|_| Utc::now(), DateTime::from);
+        let _ = self.notify_api(final_path, recorded_at).await;
+        self.remove_local_copy(final_path);
+        Ok(())
+    }
 
-            println!("   Downloaded {} MB", bytes.len() / 1_000_000);
+    /// One gRPC upload attempt, resuming where an earlier one stopped.
+    async fn upload_once(&self, path: &Path, attempt: u32) -> std::result::Result<(), String> {
+        let segment = segment_id(path);
+        let file_size = fs::metadata(path).map_err(|e| e.to_string())?.len();
+
+        // Determine file format for logging
+        let file_format = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("unknown");
+        info!(
+            segment,
+            attempt,
+            path:% = path.display(),
+            bytes = file_size,
+            format = file_format;
+            "starting upload"
+        );
 
-            std::fs::write(exe_path, &bytes)
-                .map_err(|e| format!("Failed to write file: {}", e))?;
+        let start = Instant::now();
+        let events = self.events.clone();
+        let progress_segment = segment.to_string();
+        let progress = move |sent, total| {
+            events.emit(RecordingEvent::UploadProgress {
+                segment: progress_segment.clone(),
+                sent,
+                total,
+            })
+        };
+
+        self.uploader
+            .upload(path, progress)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        info!(
+            segment,
+            attempt,
+            elapsed_ms = start.elapsed().as_millis() as u64;
+            "upload successful"
+        );
+        self.events.emit(RecordingEvent::UploadProgress {
+            segment: segment.to_string(),
+            sent: file_size,
+            total: file_size,
+        });
+        Ok(())
+    }
 
-            Ok(())
+    /// Tell the API about an uploaded segment finished at `recorded_at`.
+    async fn notify_api(&self, path: &Path, recorded_at: DateTime<Utc>) -> Result<()> {
+        let segment = segment_id(path);
+        let recorder_version = AppDirs::locate()
+            .ok()
+            .and_then(|dirs| updater::active_version(&dirs.bin_dir))
+            .map(|version| version.to_string());
+        let result = video_id_send_to_api_fn(
+            &self.client,
+            path,
+            self.config.user_id(),
+            self.config.api_url().as_str(),
+            recorded_at,
+            recorder_version.as_deref(),
+        )
+        .await;
+
+        match &result {
+            Ok(()) => info!(segment; "video id sent to API"),
+            Err(e) => warn!(segment, error:% = e; "failed to send video id to API"),
         }
+        self.events.emit(RecordingEvent::ApiNotified {
+            segment: segment.to_string(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
+        result
+    }
 
-        fn try_download_with_curl(url: &str, exe_path: &std::path::Path) -> Result<(), String> {
-            use std::process::Command;
-
-            let output = Command::new("curl")
-                .arg("-L")  // Follow redirects
-                .arg("-k")  // Insecure (ignore SSL)
-                .arg("-o")
-                .arg(exe_path)
-                .arg(url)
-                .arg("--connect-timeout")
-                .arg("30")
-                .arg("--max-time")
-                .arg("500")
-                .output()
-                .map_err(|e| format!("Failed to execute curl: {}", e))?;
-
-            if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                return Err(format!("Curl failed: {}", stderr));
+    /// Delete the local copy of an uploaded segment.
+    fn remove_local_copy(&self, path: &Path) {
+        let segment = segment_id(path);
+        let removed = match fs::remove_file(path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        };
+        self.events.emit(RecordingEvent::Cleanup {
+            segment: segment.to_string(),
+            path: path.to_path_buf(),
+            removed: removed.is_ok(),
+        });
+        match removed {
+            Ok(()) => {
+                debug!(segment, path:% = path.display(); "temporary file cleaned up");
             }
-
-            // Verify file was created
-            if !exe_path.exists() {
-                return Err("File was not created".to_string());
+            Err(e) => {
+                // Don't fail the process for cleanup issues
+                warn!(
+                    segment,
+                    path:% = path.display(),
+                    error:% = e;
+                    "failed to delete temporary file"
+                );
             }
+        }
+    }
+}
 
-            let size = std::fs::metadata(exe_path)
-                .map_err(|e| format!("Failed to check file: {}", e))?
-                .len();
+fn lock(queue: &Mutex<UploadQueue>) -> MutexGuard<'_, UploadQueue> {
+    queue.lock().unwrap_or_else(PoisonError::into_inner)
+}
 
-            println!("   Downloaded {} MB", size / 1_000_000);
-            Ok(())
-        }
+/// `secs` since the Unix epoch, as journaled in the upload queue.
+fn unix_time(secs: u64) -> DateTime<Utc> {
+    DateTime::from_timestamp(secs as i64, 0).unwrap_or_else(Utc::now)
+}
 
-        fn try_download_with_powershell(url: &str, exe_path: &std::path::Path) -> Result<(), String> {
-            use std::process::Command;
+/// Identifier of a segment: the recording's file name without extension.
+pub fn segment_id(path: &Path) -> &str {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default()
+}
 
-            let ps_command = format!(
-                "[Net.ServicePointManager]::SecurityProtocol = [Net.SecurityProtocolType]::Tls12; \
-                $ProgressPreference = 'SilentlyContinue'; \
-                Invoke-WebRequest -Uri '{}' -OutFile '{}' -TimeoutSec 500",
-                url,
-                exe_path.display()
-            );
+// screen_record.exe
+pub async fn process_screen_recording(
+    user_id: &str,
+    api_url: &str,
+    recorder_exe_url: &str,
+    grpc_server_ip: &str,
+    grpc_server_port: &str,
+) -> Result<()> {
+    let config = RecordingConfig::builder()
+        .user_id(user_id)
+        .api_url(api_url)
+        .recorder_url(recorder_exe_url)
+        .grpc_endpoint(format!("http://{}:{}", grpc_server_ip, grpc_server_port))
+        .build()?;
 
-            let output = Command::new("powershell")
-                .arg("-NoProfile")
-                .arg("-NonInteractive")
-                .arg("-Command")
-                .arg(&ps_command)
-                .output()
-                .map_err(|e| format!("Failed to execute PowerShell: {}", e))?;
-
-            if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                return Err(format!("PowerShell failed: {}", stderr));
-            }
+    RecordingSession::new(config)?.record_segment().await
+}
 
-            // Verify file was created
-            if !exe_path.exists() {
-                return Err("File was not created".to_string());
+/// Make sure a complete, verified recorder executable is present in `bin`,
+/// downloading it when missing, truncated or not matching its expected
+/// SHA-256, and return its path.
+///
+/// The expected digest is `recorder_sha256` when configured. Otherwise it is
+/// the digest the installed executable was last verified against, or the one
+/// published in the `<recorder_url>.sha256` manifest, whose Ed25519
+/// signature `<recorder_url>.sha256.sig` is checked when a
+/// `recorder_public_key` is configured. Without any of these the recorder is
+/// refused, unless `allow_unverified_recorder` is set.
+pub async fn ensure_recorder(
+    config: &RecordingConfig,
+    dirs: &AppDirs,
+    events: &EventSender,
+) -> Result<PathBuf> {
+    let recorder_exe_url = config
+        .recorder_url()
+        .ok_or(ConfigError::MissingField("recorder_url"))?
+        .as_str();
+    let bin_dir = &dirs.bin_dir;
+    let recorder_exe_path = dirs.recorder_exe();
+    let integrity_error = |reason| ScreenRecordError::RecorderIntegrity {
+        url: recorder_exe_url.to_string(),
+        path: recorder_exe_path.clone(),
+        reason,
+    };
+    let client = download_client(&config.recorder_tls(), config.proxy())
+        .map_err(ScreenRecordError::HttpClient)?;
+
+    if let Ok(metadata) = fs::metadata(&recorder_exe_path) {
+        let size = metadata.len();
+        if size < MIN_RECORDER_SIZE {
+            warn!(
+                path:% = recorder_exe_path.display(),
+                bytes = size;
+                "recorder executable is truncated, will re-download"
+            );
+        } else {
+            let expected = match config
+                .recorder_sha256()
+                .or_else(|| integrity::recorded_digest(&recorder_exe_path))
+            {
+                Some(digest) => Some(digest),
+                None => fetch_manifest_digest(config, &client)
+                    .await
+                    .map_err(integrity_error)?,
+            };
+            let Some(expected) = expected else {
+                if !config.allow_unverified_recorder() {
+                    return Err(integrity_error(IntegrityError::Unverified));
+                }
+                warn!(
+                    path:% = recorder_exe_path.display();
+                    "no recorder_sha256 or manifest available, running the recorder unverified"
+                );
+                return Ok(recorder_exe_path);
+            };
+            match integrity::verify_file(&recorder_exe_path, expected) {
+                Ok(()) => {
+                    debug!(
+                        path:% = recorder_exe_path.display(),
+                        bytes = size,
+                        sha256:% = expected;
+                        "using existing recorder executable"
+                    );
+                    integrity::record_digest(&recorder_exe_path, expected);
+                    return Ok(recorder_exe_path);
+                }
+                Err(IntegrityError::HashMismatch { actual, .. }) => {
+                    warn!(
+                        path:% = recorder_exe_path.display(),
+                        expected:% = expected,
+                        actual:% = actual;
+                        "recorder executable does not match its digest, will re-download"
+                    );
+                    integrity::forget_digest(&recorder_exe_path);
+                }
+                Err(e) => return Err(integrity_error(e)),
             }
+        }
+    }
+
+    let expected = match config.recorder_sha256() {
+        Some(digest) => Some(digest),
+        None => fetch_manifest_digest(config, &client)
+            .await
+            .map_err(integrity_error)?,
+    };
+    // Refuse before downloading what could not be run anyway
+    if expected.is_none() && !config.allow_unverified_recorder() {
+        return Err(integrity_error(IntegrityError::Unverified));
+    }
 
-            let size = std::fs::metadata(exe_path)
-                .map_err(|e| format!("Failed to check file: {}", e))?
-                .len();
+    info!(url = recorder_exe_url; "downloading recorder executable");
+
+    // Ensure bin directory exists
+    fs::create_dir_all(bin_dir)?;
+
+    // Download next to the executable and only move it into place once it
+    // checks out, so a failed or tampered download is never started. The
+    // part file survives failures so the next attempt can resume it.
+    let part_path = bin_dir.join(format!("{}.part", VIDEO_RECORDER_EXE));
+    let urls: Vec<&str> = config.recorder_urls().map(url::Url::as_str).collect();
+    download_recorder_exe(&client, &urls, &part_path, &RetryPolicy::default(), events)
+        .await
+        .map_err(|e| ScreenRecordError::RecorderDownload {
+            url: recorder_exe_url.to_string(),
+            failures: e.attempts.iter().map(ToString::to_string).collect(),
+        })?;
 
-            println!("   Downloaded {} MB", size / 1_000_000);
-            Ok(())
+    match expected {
+        Some(expected) => {
+            if let Err(e) = integrity::verify_file(&part_path, expected) {
+                let _ = fs::remove_file(&part_path);
+                return Err(integrity_error(e));
+            }
+            info!(sha256:% = expected; "recorder download verified");
         }
-
-        download_thread
-            .join()
-            .map_err(|_| "Download thread panicked")?
-            .map_err(|e| -> Box<dyn std::error::Error> { e.into() })?;
+        None => warn!(
+            url = recorder_exe_url;
+            "no recorder_sha256 or manifest available, the download is not verified"
+        ),
+    }
+    fs::rename(&part_path, &recorder_exe_path)?;
+    if let Some(expected) = expected {
+        integrity::record_digest(&recorder_exe_path, expected);
     }
 
     let recorder_exe = recorder_exe_path;
@@ -329,373 +825,304 @@ pub async fn process_screen_recording(
     // Verify the executable is accessible
     match fs::metadata(&recorder_exe) {
         Ok(metadata) => {
-            let size_mb = metadata.len() / 1_000_000;
-            println!("✅ Recorder executable is accessible and ready ({} MB)", size_mb);
-        }
-        Err(e) => {
-            // Build detailed error message with all checked paths
-            let mut error_msg = format!(
-                "❌ Cannot access recorder executable!\n\n\
-                 Error: {}\n\n\
-                 Expected path: {}\n\n\
-                 App directory source: {}\n\n\
-                 All checked paths:\n",
-                e,
-                recorder_exe.display(),
-                app_dir_info.source
+            info!(
+                path:% = recorder_exe.display(),
+                bytes = metadata.len();
+                "recorder executable is ready"
             );
+        }
+        Err(source) => {
+            return Err(ScreenRecordError::RecorderMissing {
+                path: recorder_exe,
+                checked_paths: dirs.info.checked_paths.clone(),
+                source,
+            });
+        }
+    }
+
+    Ok(recorder_exe)
+}
 
-            for (source, path, exists) in &app_dir_info.checked_paths {
-                let status = if *exists { "✅ EXISTS" } else { "❌ NOT FOUND" };
-                error_msg.push_str(&format!("  - [{}] {}: {}\n", status, source, path.display()));
+/// Digest published in the `<recorder_url>.sha256` manifest; `None` when
+/// there is no manifest and no public key demands one. The mirrors'
+/// manifests are asked in turn while the previous one cannot be reached.
+async fn fetch_manifest_digest(
+    config: &RecordingConfig,
+    client: &Client,
+) -> Result<Option<Sha256Digest>, IntegrityError> {
+    let mut unreachable = None;
+    for url in config.recorder_urls() {
+        match fetch_manifest_digest_from(config, client, url.as_str()).await {
+            Err(e @ IntegrityError::Manifest { .. }) => {
+                warn!(url = url.as_str(), error:% = e; "recorder manifest unavailable");
+                unreachable = Some(e);
             }
+            result => return result,
+        }
+    }
+    Err(unreachable.expect("there is always a recorder URL"))
+}
 
-            error_msg.push_str(&format!(
-                "\nPossible solutions:\n\
-                 1. Check if the download URL is accessible: {}\n\
-                 2. Ensure write permissions to: {}\n\
-                 3. Check if antivirus is blocking the download\n\
-                 4. Try manually downloading the file to: {}",
-                recorder_exe_url,
-                bin_dir.display(),
-                recorder_exe.display()
+async fn fetch_manifest_digest_from(
+    config: &RecordingConfig,
+    client: &Client,
+    recorder_url: &str,
+) -> Result<Option<Sha256Digest>, IntegrityError> {
+    let manifest_url = format!("{}.sha256", recorder_url);
+    let signature_url = format!("{}.sig", manifest_url);
+    let public_key = config.recorder_public_key();
+    let manifest_error = |url: &str, reason: String| IntegrityError::Manifest {
+        url: url.to_string(),
+        reason,
+    };
+
+    let manifest = fetch_text(client, &manifest_url)
+        .await
+        .map_err(|reason| manifest_error(&manifest_url, reason))?;
+    let Some(manifest) = manifest else {
+        if public_key.is_some() {
+            return Err(manifest_error(
+                &manifest_url,
+                "not found, but recorder_public_key requires a signed manifest".into(),
             ));
+        }
+        debug!(url = manifest_url.as_str(); "no recorder manifest published");
+        return Ok(None);
+    };
 
-            return Err(error_msg.into());
+    if let Some(key) = public_key {
+        let signature = fetch_text(client, &signature_url)
+            .await
+            .map_err(|reason| manifest_error(&signature_url, reason))?
+            .ok_or_else(|| manifest_error(&signature_url, "signature not found".into()))?;
+        if !key.verify(manifest.as_bytes(), &signature) {
+            return Err(IntegrityError::BadSignature { url: manifest_url });
         }
     }
 
-    // Create initial path with .webm extension for web compatibility
-    let initial_path = tmp_dir.join(format!("{}{}.webm", user_id, ts));
+    integrity::parse_manifest(&manifest)
+        .map(Some)
+        .ok_or(IntegrityError::MalformedManifest { url: manifest_url })
+}
 
-    let client = Client::builder()
-        .danger_accept_invalid_certs(true)
-        .connect_timeout(Duration::from_secs(15))
-        .timeout(Duration::from_secs(60))
-        .build()?;
+/// Body of `url`, or `None` for a 404.
+async fn fetch_text(client: &Client, url: &str) -> Result<Option<String>, String> {
+    let response = client
+        .get(url)
+        .timeout(Duration::from_secs(30))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(format!("HTTP error: {}", response.status()));
+    }
+    response.text().await.map(Some).map_err(|e| e.to_string())
+}
+
+/// Where a segment starting now is recorded: `<user_id><timestamp>.<ext>`
+/// in `tmp_dir`, with the configured container's extension.
+pub(crate) fn segment_path(config: &RecordingConfig, tmp_dir: &Path) -> PathBuf {
+    let ts = Utc::now().format("%Y%m%dT%H%M%S").to_string();
+    tmp_dir.join(format!(
+        "{}{}.{}",
+        config.user_id(),
+        ts,
+        config.container().extension()
+    ))
+}
 
-    println!("🎬 Starting recording to: {}", initial_path.display());
-    println!("🔧 Recorder executable: {}", recorder_exe.display());
-    println!("📁 Working directory: {}", app_dir.display());
+/// Run the recorder for one segment and return the video file it produced,
+/// together with whether it was cut short by `cancel`.
+pub(crate) async fn capture_segment(
+    config: &RecordingConfig,
+    dirs: &AppDirs,
+    recorder_exe: &Path,
+    events: &EventSender,
+    cancel: &CancellationToken,
+) -> Result<(PathBuf, bool)> {
+    let app_dir = &dirs.app_dir;
+    let tmp_dir = &dirs.tmp_dir;
+    let duration_secs = config.segment_duration().as_secs().max(1);
+
+    let initial_path = segment_path(config, tmp_dir);
+
+    let segment = segment_id(&initial_path);
 
     // Verify paths before execution
     if !tmp_dir.exists() {
-        println!("⚠️ Temp directory doesn't exist, creating: {}", tmp_dir.display());
-        fs::create_dir_all(&tmp_dir)?;
+        warn!(path:% = tmp_dir.display(); "temp directory disappeared, recreating");
+        fs::create_dir_all(tmp_dir)?;
     }
 
     // Execute the recorder with improved error handling and real-time output
-    println!("🚀 Executing recorder command...");
-    println!("⏱️  Recording will take 120 seconds (2 minutes)...");
+    info!(
+        segment,
+        path:% = initial_path.display(),
+        recorder:% = recorder_exe.display(),
+        cwd:% = app_dir.display(),
+        duration_secs;
+        "starting recording"
+    );
 
-    let mut child = Command::new(&recorder_exe)
-        .current_dir(&app_dir)
+    let child = new_process_group(&mut tokio::process::Command::new(recorder_exe))
+        .current_dir(app_dir)
+        .arg("--output")
+        .arg(&initial_path)
         .args([
-            "--output",
-            initial_path.to_str().ok_or("Invalid path")?,
             "--duration",
-            "120",
+            &duration_secs.to_string(),
             "--fps",
-            "24",
+            &config.fps().to_string(),
             "--resolution",
-            "1280x720",
+            &config.resolution().to_string(),
         ])
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
+        .kill_on_drop(true)
         .spawn()
-        .map_err(|e| {
-            format!(
-                "Failed to execute recorder at '{}': {} (Error code: {:?})\n\
-                 This might be due to:\n\
-                 1. The executable is corrupted or not a valid Windows executable\n\
-                 2. The executable is blocked by Windows (right-click > Properties > Unblock)\n\
-                 3. Missing required DLL files or dependencies\n\
-                 4. Antivirus software blocking execution\n\
-                 5. The file path contains invalid characters\n\
-                 Working directory: {}\n\
-                 Output path: {}",
-                recorder_exe.display(),
-                e,
-                e.raw_os_error(),
-                app_dir.display(),
-                initial_path.display()
-            )
+        .map_err(|source| ScreenRecordError::RecorderSpawn {
+            path: recorder_exe.to_path_buf(),
+            source,
         })?;
 
-    // Read stdout in real-time (without adding extra emojis)
-    if let Some(stdout) = child.stdout.take() {
-        let reader = BufReader::new(stdout);
-        for line in reader.lines() {
-            if let Ok(line) = line {
-                println!("{}", line);
-            }
+    // Dropped, and so forgotten, once the recorder has exited
+    let _tracked = child.id().and_then(|pid| match dirs.recorders().track(pid) {
+        Ok(tracked) => Some(tracked),
+        Err(e) => {
+            warn!(pid, error:% = e; "failed to record the recorder process, `stop` will not find it");
+            None
         }
-    }
-
-    // Wait for the process to complete
-    let output = child.wait_with_output()?;
-
-    let stderr = String::from_utf8_lossy(&output.stderr);
-
-    println!(
-        "🏁 Process completed with exit code: {:?}",
-        output.status.code()
-    );
+    });
+
+    events.emit(RecordingEvent::RecordingStarted {
+        segment: segment.to_string(),
+        path: initial_path.clone(),
+        duration: config.segment_duration(),
+    });
+
+    // Forward the recorder's output in real-time until it exits, is
+    // cancelled or overruns the segment by more than the watchdog grace
+    let deadline = config.segment_duration() + config.watchdog_grace();
+    let supervised = supervise(
+        child,
+        deadline,
+        config.stop_timeout(),
+        cancel,
+        |stream, line| {
+            match stream {
+                OutputStream::Stdout => {
+                    debug!(target: "screen_record::recorder", segment; "{}", line)
+                }
+                OutputStream::Stderr if line.trim().is_empty() => return,
+                OutputStream::Stderr => {
+                    warn!(target: "screen_record::recorder", segment; "{}", line)
+                }
+            }
+            events.emit(RecordingEvent::RecorderOutput {
+                segment: segment.to_string(),
+                line,
+            });
+        },
+    )
+    .await?;
 
-    // Show errors if any
-    if !stderr.is_empty() {
-        println!("=== RECORDER ERRORS ===");
-        println!("{}", stderr);
-        println!("=== END ERRORS ===");
+    let exit = supervised.exit;
+    let cancelled = exit == ExitKind::Cancelled;
+    match exit {
+        ExitKind::Success | ExitKind::Cancelled => info!(segment, exit:% = exit; "recorder exited"),
+        _ => warn!(segment, exit:% = exit; "recorder exited abnormally"),
     }
 
-    // Smart file detection - the Python script might create different formats
-    println!("📁 Detecting created video file...");
-
-    let possible_extensions = vec!["webm", "mp4", "avi", "mkv"];
-    let base_name = initial_path.file_stem().unwrap().to_str().unwrap();
-    let parent_dir = initial_path.parent().unwrap();
+    // The recorder may fall back to another container than the configured
+    // one, so accept this segment's file under any video extension. Other
+    // files in the temp directory are earlier segments waiting in the upload
+    // queue and are never taken for this one.
+    let mut possible_extensions = vec![config.container().extension()];
+    possible_extensions.extend(
+        Container::ALL
+            .iter()
+            .map(Container::extension)
+            .filter(|ext| *ext != config.container().extension()),
+    );
 
     let mut actual_file_path: Option<PathBuf> = None;
-
-    // First, check for files with the exact base name
     for ext in &possible_extensions {
-        let test_path = parent_dir.join(format!("{}.{}", base_name, ext));
-        println!("📁 Checking: {}", test_path.display());
-
+        let test_path = initial_path.with_extension(ext);
         if test_path.exists() {
             let file_size = fs::metadata(&test_path)?.len();
             if file_size > 0 {
-                println!(
-                    "✅ Found video file: {} ({} bytes)",
-                    test_path.display(),
-                    file_size
-                );
+                debug!(segment, path:% = test_path.display(), bytes = file_size; "found video file");
                 actual_file_path = Some(test_path);
                 break;
             } else {
-                println!("⚠️ Found empty file: {}", test_path.display());
-            }
-        }
-    }
-
-    // If no exact match, scan for recent video files in the directory
-    if actual_file_path.is_none() {
-        println!("🔍 Scanning for recent video files in temp directory...");
-
-        if let Ok(entries) = fs::read_dir(&tmp_dir) {
-            let mut recent_videos = Vec::new();
-            let now = std::time::SystemTime::now();
-
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let path = entry.path();
-
-                    if let Some(ext) = path.extension() {
-                        if possible_extensions.contains(&ext.to_str().unwrap_or("")) {
-                            if let Ok(metadata) = fs::metadata(&path) {
-                                let size = metadata.len();
-                                if let Ok(modified) = metadata.modified() {
-                                    if let Ok(duration) = now.duration_since(modified) {
-                                        // Consider files created in the last 2 minutes
-                                        if duration.as_secs() < 120 && size > 1000 {
-                                            println!(
-                                                "📹 Found recent video: {} ({} bytes)",
-                                                path.display(),
-                                                size
-                                            );
-                                            recent_videos.push((path, size, modified));
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-
-            // Use the most recently created video file
-            if !recent_videos.is_empty() {
-                recent_videos.sort_by_key(|(_, _, modified)| *modified);
-                if let Some((path, size, _)) = recent_videos.last() {
-                    println!(
-                        "✅ Using most recent video: {} ({} bytes)",
-                        path.display(),
-                        size
-                    );
-                    actual_file_path = Some(path.clone());
-                }
+                warn!(segment, path:% = test_path.display(); "found empty video file");
             }
         }
     }
 
-    // Process the found file
     match actual_file_path {
-        Some(final_path) => {
-            let file_size = fs::metadata(&final_path)?.len();
-
-            if file_size == 0 {
-                return Err("Recording file is empty".into());
-            }
-
-            println!("🎬 Recording completed successfully!");
-            println!(
-                "✅ Final video file: {} ({} bytes)",
-                final_path.display(),
-                file_size
-            );
-
-            // Determine file format for logging
-            let file_format = final_path
-                .extension()
-                .and_then(|ext| ext.to_str())
-                .unwrap_or("unknown")
-                .to_uppercase();
-            println!("📄 Format: {}", file_format);
-
-            // Start upload process
-            println!("📤 Starting upload process...");
-
-            const MAX_RETRIES: usize = 3;
-            let mut attempt = 0;
-
-            loop {
-                attempt += 1;
-                let start = Instant::now();
-                println!("🚀 Upload attempt {} of {}...", attempt, MAX_RETRIES);
-
-                match file_upload_to_grpc(
-                    &final_path.display().to_string(),
-                    grpc_server_ip,
-                    grpc_server_port,
-                )
-                .await
-                {
-                    Ok(_) => {
-                        println!("✅ Upload successful in {:.2?}", start.elapsed());
-
-                        // Send video ID to API
-                        match video_id_send_to_api_fn(&client, &final_path, user_id, api_url).await
-                        {
-                            Ok(_) => {
-                                println!("✅ Video ID sent to API successfully.");
-                            }
-                            Err(e) => {
-                                println!("⚠️ Failed to send video ID to API: {}", e);
-                                // Don't fail the entire process for API issues
-                            }
-                        }
-                        break;
-                    }
-                    Err(e) if attempt < MAX_RETRIES => {
-                        eprintln!(
-                            "⚠️ Upload failed (attempt {}): {}. Retrying in 5s...",
-                            attempt, e
-                        );
-                        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
-                    }
-                    Err(e) => {
-                        eprintln!("❌ All upload attempts failed: {}", e);
-                        return Err(e.into());
-                    }
-                }
-            }
-
-            // Clean up the file
-            match fs::remove_file(&final_path) {
-                Ok(_) => {
-                    println!("🗑️ Temporary file cleaned up");
-                }
-                Err(e) => {
-                    eprintln!(
-                        "⚠️ Failed to delete temporary file: {} — {}",
-                        final_path.display(),
-                        e
-                    );
-                    // Don't fail the process for cleanup issues
-                }
-            }
-
-            println!("🎉 Screen recording process completed successfully!");
-            Ok(())
-        }
+        Some(final_path) => Ok((final_path, cancelled)),
+        None if cancelled => Err(ScreenRecordError::Cancelled { kept: None }),
         None => {
-            eprintln!("❌ No recording file was created");
+            error!(segment, exit:% = exit; "no recording file was created");
 
             // List all files for debugging
-            println!("📂 Files in temp directory:");
-            if let Ok(entries) = fs::read_dir(&tmp_dir) {
-                for entry in entries {
-                    if let Ok(entry) = entry {
-                        let path = entry.path();
-                        if let Ok(metadata) = fs::metadata(&path) {
-                            println!("  - {} ({} bytes)", path.display(), metadata.len());
-                        }
+            if let Ok(entries) = fs::read_dir(tmp_dir) {
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    if let Ok(metadata) = fs::metadata(&path) {
+                        debug!(path:% = path.display(), bytes = metadata.len(); "temp directory entry");
                     }
                 }
             }
 
-            Err(format!(
-                "Recording failed: No video file was created\nExit code: {:?}",
-                output.status.code()
-            )
-            .into())
+            if exit == ExitKind::Success {
+                Err(ScreenRecordError::NoOutputFile {
+                    dir: tmp_dir.to_path_buf(),
+                })
+            } else {
+                Err(ScreenRecordError::RecorderExit {
+                    exit,
+                    stderr: supervised.stderr_tail,
+                })
+            }
         }
     }
 }
 
-// Utility function to stop any running recorder processes
-pub fn stop_recorder() -> io::Result<()> {
-    match is_process_running(VIDEO_RECORDER_EXE) {
-        Ok(true) => {
-            println!("🛑 Stopping running recorder process...");
-            kill_process_by_name(VIDEO_RECORDER_EXE)
-        }
-        Ok(false) => {
-            println!("ℹ️ No recorder process is currently running.");
-            Ok(())
-        }
-        Err(e) => {
-            eprintln!("❌ Error checking for running processes: {}", e);
-            Err(e)
-        }
+/// Stop the recorders spawned by agents of this user, asking each to exit
+/// before killing it after `timeout`, and return how many were running.
+///
+/// Only processes recorded in the app directory's `run` directory are
+/// touched, so recorders of other users or unrelated programs with the same
+/// name are left alone.
+pub async fn stop_recorder(dirs: &AppDirs, timeout: Duration) -> io::Result<usize> {
+    let running = dirs.recorders().running()?;
+    if running.is_empty() {
+        info!("no recorder process is running");
+        return Ok(0);
     }
-}
-
-fn is_process_running(exe_name: &str) -> io::Result<bool> {
-    let output = Command::new("tasklist")
-        .args(&["/FI", &format!("IMAGENAME eq {}", exe_name)])
-        .stdout(Stdio::piped())
-        .stderr(Stdio::null())
-        .output()?;
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    Ok(stdout.to_lowercase().contains(&exe_name.to_lowercase()))
-}
-
-fn kill_process_by_name(exe_name: &str) -> io::Result<()> {
-    let status = Command::new("taskkill")
-        .args(&["/F", "/IM", exe_name])
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()?;
-
-    if status.success() {
-        println!("✅ Successfully stopped {}", exe_name);
-    } else {
-        eprintln!(
-            "⚠️ Failed to stop {} (exit code: {:?})",
-            exe_name,
-            status.code()
-        );
+    for process in &running {
+        info!(pid = process.pid; "stopping recorder process");
+        match stop_process(*process, timeout).await {
+            Ok(false) => info!(pid = process.pid; "stopped recorder process"),
+            Ok(true) => warn!(pid = process.pid; "killed recorder process"),
+            Err(e) => {
+                error!(pid = process.pid, error:% = e; "failed to stop recorder process");
+                return Err(e);
+            }
+        }
     }
-    Ok(())
+    Ok(running.len())
 }
 
 // Test function for development
 #[cfg(debug_assertions)]
-pub async fn test_recording() -> Result<(), Box<dyn std::error::Error>> {
-    println!("🧪 Running test recording...");
+pub async fn test_recording() -> Result<()> {
+    info!("running test recording");
 
     process_screen_recording(
         "test_user",
@@ -0,0 +1,183 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use log::warn;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::ring;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+
+/// SHA-256 hash of a certificate's SubjectPublicKeyInfo, written as
+/// `sha256/<base64>` like HPKP and curl's `--pinnedpubkey`. Get it with
+///
+/// ```text
+/// openssl x509 -in cert.pem -pubkey -noout | openssl pkey -pubin -outform der \
+///     | openssl dgst -sha256 -binary | base64
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpkiPin([u8; 32]);
+
+impl SpkiPin {
+    /// Pin for a DER-encoded SubjectPublicKeyInfo.
+    pub fn of_spki(spki: &[u8]) -> Self {
+        Self(Sha256::digest(spki).into())
+    }
+
+    /// Pin for the public key of a DER-encoded certificate.
+    pub fn of_certificate(der: &[u8]) -> Option<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+        Some(Self::of_spki(cert.public_key().raw))
+    }
+}
+
+impl FromStr for SpkiPin {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = s.trim().strip_prefix("sha256/").ok_or(())?;
+        let bytes = BASE64.decode(encoded).map_err(|_| ())?;
+        Ok(Self(bytes.try_into().map_err(|_| ())?))
+    }
+}
+
+impl fmt::Display for SpkiPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sha256/{}", BASE64.encode(self.0))
+    }
+}
+
+impl fmt::Debug for SpkiPin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// How an HTTPS client checks the server it talks to.
+///
+/// Certificates are verified against the system roots unless `insecure` is
+/// set. With `pins`, the verified chain must also contain a certificate whose
+/// public key matches one of them. `insecure` skips the pins as well, which
+/// is why [`RecordingConfig`](crate::config::RecordingConfig) refuses to
+/// combine the two.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsPolicy {
+    pub insecure: bool,
+    pub pins: Vec<SpkiPin>,
+}
+
+impl TlsPolicy {
+    /// Apply the policy to a client; `purpose` names the connection in the
+    /// warning logged for insecure mode.
+    pub fn apply(&self, builder: reqwest::ClientBuilder, purpose: &str) -> reqwest::ClientBuilder {
+        if self.insecure {
+            warn_insecure(purpose);
+            builder.danger_accept_invalid_certs(true)
+        } else if self.pins.is_empty() {
+            builder
+        } else {
+            builder.use_preconfigured_tls(pinned_config(&self.pins))
+        }
+    }
+}
+
+fn warn_insecure(purpose: &str) {
+    warn!(
+        purpose;
+        "TLS CERTIFICATE VERIFICATION IS DISABLED (insecure_tls = true); anyone on the \
+         network path can read or replace this traffic"
+    );
+}
+
+/// A rustls config trusting the system roots and checking `pins`.
+fn pinned_config(pins: &[SpkiPin]) -> rustls::ClientConfig {
+    let provider = Arc::new(ring::default_provider());
+
+    let mut roots = RootCertStore::empty();
+    let native = rustls_native_certs::load_native_certs();
+    for error in &native.errors {
+        warn!(error:% = error; "failed to load a system root certificate");
+    }
+    roots.add_parsable_certificates(native.certs);
+
+    let verifier = PinningVerifier {
+        inner: WebPkiServerVerifier::builder_with_provider(Arc::new(roots), Arc::clone(&provider))
+            .build()
+            .expect("the ring provider supports webpki verification"),
+        pins: pins.to_vec(),
+    };
+
+    let mut config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .expect("the ring provider supports the default protocol versions")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    config
+}
+
+/// Normal WebPKI verification plus a public key pin check on the chain.
+#[derive(Debug)]
+struct PinningVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<SpkiPin>,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(|cert| SpkiPin::of_certificate(cert))
+            .any(|pin| self.pins.contains(&pin));
+        if pinned {
+            Ok(verified)
+        } else {
+            Err(rustls::Error::General(format!(
+                "no certificate presented by {:?} matches a configured public key pin",
+                server_name
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
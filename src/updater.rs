@@ -0,0 +1,420 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::{debug, info, warn};
+use reqwest::Client;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use crate::config::RecordingConfig;
+use crate::error::{Result, ScreenRecordError};
+use crate::events::{EventSender, RecordingEvent};
+use crate::integrity::{self, Sha256Digest};
+use crate::modules::api::download::{download_client, download_recorder_exe, RetryPolicy};
+use crate::run::VIDEO_RECORDER_EXE;
+
+/// File in `bin` naming the active and the previous recorder version.
+pub const STATE_FILE: &str = "recorder.json";
+
+/// Held exclusively in `bin` by [`update`] and [`rollback`], so a `recorder`
+/// command never changes the installed versions under a running agent.
+pub const LOCK_FILE: &str = "recorder.lock";
+
+/// Held by [`update`] before [`LOCK_FILE`], so the periodic check and a
+/// recording that finds no active recorder wait for each other instead of
+/// failing on the file lock.
+static INSTALL: Mutex<()> = Mutex::const_new(());
+
+/// The manifest served at `recorder_manifest_url`, e.g.
+///
+/// ```json
+/// {
+///   "version": "1.4.0",
+///   "min_agent_version": "0.1.3",
+///   "platforms": {
+///     "windows-x86_64": { "url": "https://…/1.4.0/screen_record.exe", "sha256": "…" }
+///   }
+/// }
+/// ```
+///
+/// With `recorder_public_key` set, `<recorder_manifest_url>.sig` must hold a
+/// base64 Ed25519 signature over the manifest bytes.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RecorderManifest {
+    pub version: Version,
+    #[serde(default)]
+    pub min_agent_version: Option<Version>,
+    /// Builds keyed by [`platform`].
+    pub platforms: BTreeMap<String, PlatformBuild>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PlatformBuild {
+    pub url: Url,
+    pub sha256: Sha256Digest,
+}
+
+/// Recorder versions installed under `bin/<version>/`, see [`STATE_FILE`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstalledRecorders {
+    pub current: Option<Version>,
+    /// Kept on disk for [`rollback`].
+    pub previous: Option<Version>,
+    /// Version rolled back from; not reinstalled until the manifest moves on.
+    #[serde(default)]
+    pub rejected: Option<Version>,
+}
+
+impl InstalledRecorders {
+    /// The recorded state, empty when nothing was installed from a manifest.
+    pub fn load(bin_dir: &Path) -> io::Result<Self> {
+        match fs::read(bin_dir.join(STATE_FILE)) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write through a temp file and a rename so a recording never sees a
+    /// half-written state.
+    fn save(&self, bin_dir: &Path) -> io::Result<()> {
+        let path = bin_dir.join(STATE_FILE);
+        let tmp = bin_dir.join(format!("{}.{}.tmp", STATE_FILE, std::process::id()));
+        fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&tmp, &path)
+    }
+}
+
+/// Key of this machine's build in [`RecorderManifest::platforms`], e.g.
+/// `windows-x86_64`.
+pub fn platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+pub fn agent_version() -> Version {
+    env!("CARGO_PKG_VERSION")
+        .parse()
+        .expect("the package version is valid semver")
+}
+
+/// Where recorder `version` is installed.
+pub fn recorder_path(bin_dir: &Path, version: &Version) -> PathBuf {
+    bin_dir.join(version.to_string()).join(VIDEO_RECORDER_EXE)
+}
+
+/// The active recorder version, if one was installed from a manifest.
+pub fn active_version(bin_dir: &Path) -> Option<Version> {
+    InstalledRecorders::load(bin_dir).ok()?.current
+}
+
+/// Path of the active recorder, installing it from the manifest when none
+/// is active yet or the active one no longer matches the digest it was
+/// installed with.
+pub async fn active_recorder(
+    config: &RecordingConfig,
+    manifest_url: &Url,
+    bin_dir: &Path,
+    events: &EventSender,
+) -> Result<PathBuf> {
+    if let Some(exe) = verified_current(bin_dir)? {
+        return Ok(exe);
+    }
+    update(config, manifest_url, bin_dir, events).await?;
+    verified_current(bin_dir)?.ok_or_else(|| ScreenRecordError::RecorderUpdate {
+        url: manifest_url.to_string(),
+        reason: "no verified recorder version is active".into(),
+    })
+}
+
+/// The active recorder, if it still matches the digest it was installed with.
+fn verified_current(bin_dir: &Path) -> Result<Option<PathBuf>> {
+    let Some(version) = InstalledRecorders::load(bin_dir)?.current else {
+        return Ok(None);
+    };
+    let exe = recorder_path(bin_dir, &version);
+    match integrity::recorded_digest(&exe) {
+        Some(digest) if integrity::verify_file(&exe, digest).is_ok() => {
+            debug!(version:% = version, path:% = exe.display(); "using active recorder");
+            Ok(Some(exe))
+        }
+        _ => {
+            warn!(
+                version:% = version,
+                path:% = exe.display();
+                "active recorder is missing or modified, reinstalling"
+            );
+            integrity::forget_digest(&exe);
+            Ok(None)
+        }
+    }
+}
+
+/// Fetch the manifest and make its version the active recorder, downloading
+/// and verifying it into `bin/<version>/` first when needed. The previous
+/// version is kept for [`rollback`]; older ones are removed. A manifest
+/// older than the active version is refused, so a replayed one cannot
+/// downgrade the recorder; only [`rollback`] goes back.
+///
+/// Returns the newly activated version, or `None` when nothing changed.
+pub async fn update(
+    config: &RecordingConfig,
+    manifest_url: &Url,
+    bin_dir: &Path,
+    events: &EventSender,
+) -> Result<Option<Version>> {
+    let _installing = INSTALL.lock().await;
+    let _lock = lock_bin_dir(bin_dir)?;
+    let manifest = fetch_manifest(config, manifest_url).await?;
+    let update_error = |reason: String| ScreenRecordError::RecorderUpdate {
+        url: manifest_url.to_string(),
+        reason,
+    };
+
+    let agent = agent_version();
+    if let Some(required) = &manifest.min_agent_version {
+        if agent < *required {
+            return Err(ScreenRecordError::AgentTooOld {
+                version: manifest.version.to_string(),
+                required: required.to_string(),
+                current: agent.to_string(),
+            });
+        }
+    }
+
+    let platform = platform();
+    let build = manifest
+        .platforms
+        .get(&platform)
+        .ok_or_else(|| update_error(format!("no build for platform {}", platform)))?;
+
+    let mut state = InstalledRecorders::load(bin_dir)?;
+    let version = manifest.version;
+    if state.rejected.as_ref() == Some(&version) {
+        debug!(version:% = version; "manifest still offers a rolled back recorder, keeping the current one");
+        return Ok(None);
+    }
+    if let Some(current) = state.current.as_ref().filter(|current| version < **current) {
+        return Err(update_error(format!(
+            "manifest offers {}, older than the active {}; refusing to downgrade",
+            version, current
+        )));
+    }
+
+    let exe = recorder_path(bin_dir, &version);
+    let installed = integrity::verify_file(&exe, build.sha256).is_ok();
+    if installed && state.current.as_ref() == Some(&version) {
+        debug!(version:% = version; "recorder is up to date");
+        return Ok(None);
+    }
+    if !installed {
+        install(config, &build.url, build.sha256, &exe, events).await?;
+    }
+    integrity::record_digest(&exe, build.sha256);
+
+    let previous = state.current.take().filter(|current| *current != version);
+    state = InstalledRecorders {
+        current: Some(version.clone()),
+        previous: previous
+            .or(state.previous)
+            .filter(|previous| *previous != version),
+        rejected: None,
+    };
+    state.save(bin_dir)?;
+    prune(bin_dir, &state);
+
+    info!(
+        version:% = version,
+        previous = state.previous.as_ref().map(Version::to_string);
+        "recorder version activated"
+    );
+    events.emit(RecordingEvent::RecorderUpdated {
+        version: version.to_string(),
+        previous: state.previous.as_ref().map(Version::to_string),
+    });
+    Ok(Some(version))
+}
+
+/// Switch back to the previous recorder version and stop [`update`] from
+/// reinstalling the current one until the manifest offers another version.
+/// Returns the version now active.
+pub fn rollback(bin_dir: &Path) -> Result<Version> {
+    let _lock = lock_bin_dir(bin_dir)?;
+    let state = InstalledRecorders::load(bin_dir)?;
+    let previous = state
+        .previous
+        .clone()
+        .filter(|previous| recorder_path(bin_dir, previous).exists())
+        .ok_or(ScreenRecordError::NoPreviousRecorder)?;
+
+    let state = InstalledRecorders {
+        current: Some(previous.clone()),
+        previous: state.current.clone(),
+        rejected: state.current,
+    };
+    state.save(bin_dir)?;
+    info!(
+        version:% = previous,
+        rejected = state.rejected.as_ref().map(Version::to_string);
+        "rolled back recorder"
+    );
+    Ok(previous)
+}
+
+/// Check the manifest every [`RecordingConfig::update_interval`] until
+/// `cancel` fires. Recordings pick up a new version with their next segment.
+pub async fn run_updates(
+    config: RecordingConfig,
+    manifest_url: Url,
+    bin_dir: PathBuf,
+    events: EventSender,
+    cancel: CancellationToken,
+) {
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(config.update_interval()) => {}
+            _ = cancel.cancelled() => return,
+        }
+        if let Err(e) = update(&config, &manifest_url, &bin_dir, &events).await {
+            warn!(error:% = e, hint = e.hint().unwrap_or_default(); "recorder update check failed");
+        }
+    }
+}
+
+async fn fetch_manifest(config: &RecordingConfig, url: &Url) -> Result<RecorderManifest> {
+    let update_error = |reason: String| ScreenRecordError::RecorderUpdate {
+        url: url.to_string(),
+        reason,
+    };
+    let client = config
+        .recorder_tls()
+        .apply(config.proxy().apply(Client::builder()), "recorder manifest")
+        .connect_timeout(config.connect_timeout())
+        .timeout(std::time::Duration::from_secs(60))
+        .build()
+        .map_err(ScreenRecordError::HttpClient)?;
+
+    let manifest = fetch_bytes(&client, url.as_str())
+        .await
+        .map_err(update_error)?;
+    if let Some(key) = config.recorder_public_key() {
+        let signature_url = format!("{}.sig", url);
+        let signature = fetch_bytes(&client, &signature_url)
+            .await
+            .map_err(|reason| update_error(format!("signature {}: {}", signature_url, reason)))?;
+        if !key.verify(&manifest, &String::from_utf8_lossy(&signature)) {
+            return Err(update_error(
+                "manifest signature does not match recorder_public_key".into(),
+            ));
+        }
+    }
+
+    serde_json::from_slice(&manifest).map_err(|e| update_error(format!("invalid manifest: {}", e)))
+}
+
+async fn fetch_bytes(client: &Client, url: &str) -> std::result::Result<Vec<u8>, String> {
+    let response = client.get(url).send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("HTTP error: {}", response.status()));
+    }
+    Ok(response.bytes().await.map_err(|e| e.to_string())?.to_vec())
+}
+
+/// Download `url` to `exe`, moving it into place only once it hashes to
+/// `sha256`. The part file is named after this process, so an agent and a
+/// `recorder update` run never write the same one.
+async fn install(
+    config: &RecordingConfig,
+    url: &Url,
+    sha256: Sha256Digest,
+    exe: &Path,
+    events: &EventSender,
+) -> Result<()> {
+    let dir = exe.parent().expect("recorder path has a version directory");
+    fs::create_dir_all(dir)?;
+    let part = dir.join(format!(
+        "{}.{}.part",
+        VIDEO_RECORDER_EXE,
+        std::process::id()
+    ));
+
+    info!(url = url.as_str(), path:% = exe.display(); "downloading recorder version");
+    let client = download_client(&config.recorder_tls(), config.proxy())
+        .map_err(ScreenRecordError::HttpClient)?;
+    download_recorder_exe(
+        &client,
+        &[url.as_str()],
+        &part,
+        &RetryPolicy::default(),
+        events,
+    )
+    .await
+    .map_err(|e| ScreenRecordError::RecorderDownload {
+        url: url.to_string(),
+        failures: e.attempts.iter().map(ToString::to_string).collect(),
+    })?;
+
+    if let Err(reason) = integrity::verify_file(&part, sha256) {
+        let _ = fs::remove_file(&part);
+        return Err(ScreenRecordError::RecorderIntegrity {
+            url: url.to_string(),
+            path: exe.to_path_buf(),
+            reason,
+        });
+    }
+    fs::rename(&part, exe)?;
+    Ok(())
+}
+
+/// Remove version directories other than the current and previous one.
+/// Take [`LOCK_FILE`] in `bin_dir` without waiting, creating the directory
+/// if needed.
+fn lock_bin_dir(bin_dir: &Path) -> io::Result<File> {
+    fs::create_dir_all(bin_dir)?;
+    let path = bin_dir.join(LOCK_FILE);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            format!(
+                "the recorder is being updated by another screen_record process ({} is locked)",
+                path.display()
+            ),
+        )),
+        Err(TryLockError::Error(e)) => Err(e),
+    }
+}
+
+fn prune(bin_dir: &Path, state: &InstalledRecorders) {
+    let Ok(entries) = fs::read_dir(bin_dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Some(version) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse::<Version>().ok())
+        else {
+            continue;
+        };
+        if state.current.as_ref() == Some(&version) || state.previous.as_ref() == Some(&version) {
+            continue;
+        }
+        match fs::remove_dir_all(entry.path()) {
+            Ok(()) => debug!(version:% = version; "removed old recorder version"),
+            Err(e) => {
+                warn!(version:% = version, error:% = e; "failed to remove old recorder version")
+            }
+        }
+    }
+}
@@ -0,0 +1,383 @@
+//! Recorder downloads against a minimal local HTTP/1.1 server that can cut
+//! connections short, may or may not honour `Range` and can fail outright.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use screen_record::events::{EventSender, RecordingEvent};
+use screen_record::modules::api::download::{
+    download_client, download_recorder_exe, DownloadAttempt, RetryPolicy,
+};
+use screen_record::proxy::ProxyConfig;
+use screen_record::tls::TlsPolicy;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const FILE_SIZE: usize = 3_000_000;
+
+#[derive(Clone, Copy)]
+struct Behaviour {
+    /// Close the first response after this many body bytes.
+    cut_first_after: Option<usize>,
+    supports_range: bool,
+    /// Answer every request with this status and no body.
+    fail_with: Option<u16>,
+    /// Sent as `ETag`; a request whose `If-Range` differs gets the whole file.
+    etag: Option<&'static str>,
+    /// Serve a different file for the same URL.
+    release: u8,
+    /// Stop accepting connections after the first one.
+    only_once: bool,
+}
+
+impl Default for Behaviour {
+    fn default() -> Self {
+        Self {
+            cut_first_after: None,
+            supports_range: true,
+            fail_with: None,
+            etag: None,
+            release: 0,
+            only_once: false,
+        }
+    }
+}
+
+/// Served file and the `Range` header of every request (`None` for none,
+/// or for one whose `If-Range` no longer matches).
+struct Server {
+    url: String,
+    body: Arc<Vec<u8>>,
+    ranges: Arc<Mutex<Vec<Option<u64>>>>,
+}
+
+async fn start_server(behaviour: Behaviour) -> Server {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!(
+        "http://{}/screen_record.exe",
+        listener.local_addr().unwrap()
+    );
+    let release = usize::from(behaviour.release);
+    let body: Arc<Vec<u8>> = Arc::new(
+        (0..FILE_SIZE)
+            .map(|i| ((i + release) % 251) as u8)
+            .collect(),
+    );
+    let ranges = Arc::new(Mutex::new(Vec::new()));
+
+    let (served, seen) = (Arc::clone(&body), Arc::clone(&ranges));
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let first = seen.lock().unwrap().is_empty();
+            let range = read_range(&stream, behaviour.etag).await;
+            seen.lock().unwrap().push(range);
+            let cut = behaviour.cut_first_after.filter(|_| first);
+            tokio::spawn(respond(stream, Arc::clone(&served), range, behaviour, cut));
+            if behaviour.only_once {
+                break;
+            }
+        }
+    });
+
+    Server { url, body, ranges }
+}
+
+/// Read the request head and return the start of a `Range: bytes=N-` header,
+/// unless an `If-Range` header does not match `etag`.
+async fn read_range(stream: &TcpStream, etag: Option<&str>) -> Option<u64> {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.readable().await.unwrap();
+        match stream.try_read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) => head.push(byte[0]),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            Err(e) => panic!("{}", e),
+        }
+    }
+    let head = String::from_utf8_lossy(&head);
+    let if_range = head.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("if-range").then(|| value.trim())
+    });
+    if if_range.is_some() && if_range != etag {
+        return None;
+    }
+    head.lines().find_map(|line| {
+        let value = line.to_ascii_lowercase();
+        let value = value.strip_prefix("range: bytes=")?;
+        value.trim_end_matches('-').parse().ok()
+    })
+}
+
+async fn respond(
+    mut stream: TcpStream,
+    body: Arc<Vec<u8>>,
+    range: Option<u64>,
+    behaviour: Behaviour,
+    cut: Option<usize>,
+) {
+    if let Some(status) = behaviour.fail_with {
+        let head = format!(
+            "HTTP/1.1 {} Failed\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            status
+        );
+        let _ = stream.write_all(head.as_bytes()).await;
+        return;
+    }
+    let start = range.filter(|_| behaviour.supports_range).unwrap_or(0) as usize;
+    let etag = behaviour
+        .etag
+        .map_or(String::new(), |etag| format!("ETag: {}\r\n", etag));
+    let head = if start > 0 {
+        format!(
+            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\n{}Connection: close\r\n\r\n",
+            body.len() - start,
+            start,
+            body.len() - 1,
+            body.len(),
+            etag
+        )
+    } else {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n",
+            body.len(),
+            etag
+        )
+    };
+    let end = cut.map_or(body.len(), |cut| (start + cut).min(body.len()));
+    stream.write_all(head.as_bytes()).await.unwrap();
+    let _ = stream.write_all(&body[start..end]).await;
+    let _ = stream.shutdown().await;
+    // Drain so the client sees a clean close rather than a reset
+    let _ = stream.read(&mut [0u8; 1]).await;
+}
+
+fn part_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "screen_record_download_{}_{}.part",
+        std::process::id(),
+        name
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+const QUICK_RETRY: RetryPolicy = RetryPolicy {
+    attempts: 3,
+    initial_delay: Duration::from_millis(10),
+    max_delay: Duration::from_millis(40),
+};
+
+async fn download(server: &Server, part: &Path) -> (Result<u64, String>, Vec<RecordingEvent>) {
+    let (result, events) = download_mirrors(&[&server.url], part).await;
+    (result.map_err(|attempts| format!("{:?}", attempts)), events)
+}
+
+async fn download_mirrors(
+    urls: &[&str],
+    part: &Path,
+) -> (Result<u64, Vec<DownloadAttempt>>, Vec<RecordingEvent>) {
+    let events = EventSender::new();
+    let mut receiver = events.subscribe();
+    let client = download_client(&TlsPolicy::default(), &ProxyConfig::none()).unwrap();
+    let result = download_recorder_exe(&client, urls, part, &QUICK_RETRY, &events)
+        .await
+        .map_err(|e| e.attempts);
+
+    let mut seen = Vec::new();
+    while let Ok(event) = receiver.try_recv() {
+        seen.push(event);
+    }
+    (result, seen)
+}
+
+#[tokio::test]
+async fn downloads_the_whole_file_and_reports_progress() {
+    let server = start_server(Behaviour::default()).await;
+    let part = part_file("whole");
+
+    let (result, events) = download(&server, &part).await;
+
+    assert_eq!(result.unwrap(), FILE_SIZE as u64);
+    assert_eq!(std::fs::read(&part).unwrap(), *server.body);
+    assert_eq!(
+        events.last(),
+        Some(&RecordingEvent::RecorderDownloading {
+            bytes: FILE_SIZE as u64,
+            total: Some(FILE_SIZE as u64),
+        })
+    );
+    assert!(
+        events.len() > 2,
+        "expected intermediate progress, got {:?}",
+        events
+    );
+    std::fs::remove_file(&part).unwrap();
+}
+
+#[tokio::test]
+async fn resumes_an_interrupted_download_with_a_range_request() {
+    let server = start_server(Behaviour {
+        cut_first_after: Some(1_000_000),
+        ..Behaviour::default()
+    })
+    .await;
+    let part = part_file("interrupted");
+
+    let (result, _) = download(&server, &part).await;
+
+    assert_eq!(result.unwrap(), FILE_SIZE as u64);
+    assert_eq!(std::fs::read(&part).unwrap(), *server.body);
+    assert_eq!(*server.ranges.lock().unwrap(), vec![None, Some(1_000_000)]);
+    std::fs::remove_file(&part).unwrap();
+}
+
+#[tokio::test]
+async fn resumes_while_the_etag_still_matches() {
+    let server = start_server(Behaviour {
+        cut_first_after: Some(1_000_000),
+        etag: Some("\"v1\""),
+        ..Behaviour::default()
+    })
+    .await;
+    let part = part_file("same_etag");
+
+    let (result, _) = download(&server, &part).await;
+
+    assert_eq!(result.unwrap(), FILE_SIZE as u64);
+    assert_eq!(std::fs::read(&part).unwrap(), *server.body);
+    assert_eq!(*server.ranges.lock().unwrap(), vec![None, Some(1_000_000)]);
+    std::fs::remove_file(&part).unwrap();
+}
+
+#[tokio::test]
+async fn starts_over_when_the_file_changed_between_attempts() {
+    // The first release goes away after sending part of the file
+    let old = start_server(Behaviour {
+        cut_first_after: Some(1_000_000),
+        etag: Some("\"v1\""),
+        only_once: true,
+        ..Behaviour::default()
+    })
+    .await;
+    let part = part_file("changed");
+    let (result, _) = download(&old, &part).await;
+    assert!(result.is_err());
+    assert_eq!(std::fs::metadata(&part).unwrap().len(), 1_000_000);
+
+    let new = start_server(Behaviour {
+        etag: Some("\"v2\""),
+        release: 1,
+        ..Behaviour::default()
+    })
+    .await;
+    let (result, _) = download(&new, &part).await;
+
+    assert_eq!(result.unwrap(), FILE_SIZE as u64);
+    assert_eq!(std::fs::read(&part).unwrap(), *new.body);
+    // The range was sent but did not apply to the new file
+    assert_eq!(*new.ranges.lock().unwrap(), vec![None]);
+    std::fs::remove_file(&part).unwrap();
+}
+
+#[tokio::test]
+async fn continues_a_part_file_left_by_an_earlier_run() {
+    let server = start_server(Behaviour::default()).await;
+    let part = part_file("leftover");
+    std::fs::write(&part, &server.body[..12_345]).unwrap();
+
+    let (result, _) = download(&server, &part).await;
+
+    assert_eq!(result.unwrap(), FILE_SIZE as u64);
+    assert_eq!(std::fs::read(&part).unwrap(), *server.body);
+    assert_eq!(*server.ranges.lock().unwrap(), vec![Some(12_345)]);
+    std::fs::remove_file(&part).unwrap();
+}
+
+#[tokio::test]
+async fn starts_over_when_the_server_ignores_ranges() {
+    let server = start_server(Behaviour {
+        supports_range: false,
+        ..Behaviour::default()
+    })
+    .await;
+    let part = part_file("no_range");
+    std::fs::write(&part, b"stale bytes from another build").unwrap();
+
+    let (result, _) = download(&server, &part).await;
+
+    assert_eq!(result.unwrap(), FILE_SIZE as u64);
+    assert_eq!(std::fs::read(&part).unwrap(), *server.body);
+    std::fs::remove_file(&part).unwrap();
+}
+
+#[tokio::test]
+async fn falls_back_to_the_next_mirror() {
+    let missing = start_server(Behaviour {
+        fail_with: Some(404),
+        ..Behaviour::default()
+    })
+    .await;
+    let mirror = start_server(Behaviour::default()).await;
+    let part = part_file("mirror");
+
+    let (result, _) = download_mirrors(&[&missing.url, &mirror.url], &part).await;
+
+    assert_eq!(result.unwrap(), FILE_SIZE as u64);
+    assert_eq!(std::fs::read(&part).unwrap(), *mirror.body);
+    // A client error is not retried on the same URL
+    assert_eq!(missing.ranges.lock().unwrap().len(), 1);
+    std::fs::remove_file(&part).unwrap();
+}
+
+#[tokio::test]
+async fn reports_every_attempt_when_all_mirrors_fail() {
+    let unavailable = start_server(Behaviour {
+        fail_with: Some(503),
+        ..Behaviour::default()
+    })
+    .await;
+    let missing = start_server(Behaviour {
+        fail_with: Some(404),
+        ..Behaviour::default()
+    })
+    .await;
+    let part = part_file("all_fail");
+
+    let (result, events) = download_mirrors(&[&unavailable.url, &missing.url], &part).await;
+
+    let attempts = result.unwrap_err();
+    let tried: Vec<(&str, u32)> = attempts
+        .iter()
+        .map(|attempt| (attempt.url.as_str(), attempt.attempt))
+        .collect();
+    assert_eq!(
+        tried,
+        vec![
+            (unavailable.url.as_str(), 1),
+            (unavailable.url.as_str(), 2),
+            (unavailable.url.as_str(), 3),
+            (missing.url.as_str(), 1),
+        ]
+    );
+    assert!(attempts[0].error.as_deref().unwrap().contains("503"));
+    assert!(attempts[3].error.as_deref().unwrap().contains("404"));
+
+    // Backoff doubles between the retries of one URL
+    let delays: Vec<Duration> = events
+        .iter()
+        .filter_map(|event| match event {
+            RecordingEvent::RecorderDownloadRetry { delay, .. } => Some(*delay),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        delays,
+        vec![Duration::from_millis(10), Duration::from_millis(20)]
+    );
+    let _ = std::fs::remove_file(&part);
+}
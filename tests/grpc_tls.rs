@@ -0,0 +1,209 @@
+//! Uploads over TLS and mutual TLS against a local server using the
+//! self-signed certificates in `tests/certs` (CA-signed, valid for
+//! `upload.test` only).
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use screen_record::config::GrpcTls;
+use screen_record::modules::api::grpc_upload::proto::upload_request::Type;
+use screen_record::modules::api::grpc_upload::proto::upload_service_server::{
+    UploadService, UploadServiceServer,
+};
+use screen_record::modules::api::grpc_upload::proto::{
+    QueryOffsetRequest, QueryOffsetResponse, UploadRequest, UploadResponse,
+};
+use screen_record::modules::api::grpc_upload::{UploadOptions, Uploader};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig, Uri};
+use tonic::{Request, Response, Status, Streaming};
+
+const FILE_SIZE: usize = 300_000;
+
+#[derive(Default)]
+struct CountingServer {
+    received: Arc<AtomicU64>,
+}
+
+#[tonic::async_trait]
+impl UploadService for CountingServer {
+    async fn upload_file(
+        &self,
+        request: Request<Streaming<UploadRequest>>,
+    ) -> Result<Response<UploadResponse>, Status> {
+        let mut stream = request.into_inner();
+        let mut received = 0;
+        while let Some(message) = stream.message().await? {
+            if let Some(Type::Data(chunk)) = message.r#type {
+                received += chunk.data.len() as u64;
+            }
+        }
+        self.received.store(received, Ordering::SeqCst);
+        Ok(Response::new(UploadResponse {
+            message: "stored".into(),
+            received,
+        }))
+    }
+
+    async fn query_offset(
+        &self,
+        _request: Request<QueryOffsetRequest>,
+    ) -> Result<Response<QueryOffsetResponse>, Status> {
+        Ok(Response::new(QueryOffsetResponse { offset: 0 }))
+    }
+}
+
+fn cert(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/certs")
+        .join(name)
+}
+
+fn read(name: &str) -> Vec<u8> {
+    std::fs::read(cert(name)).unwrap()
+}
+
+/// Start a TLS server on a free port; with `require_client_cert` it only
+/// accepts clients signed by the test CA.
+async fn start_server(require_client_cert: bool) -> (Uri, Arc<AtomicU64>) {
+    let mut tls =
+        ServerTlsConfig::new().identity(Identity::from_pem(read("server.pem"), read("server.key")));
+    if require_client_cert {
+        tls = tls.client_ca_root(Certificate::from_pem(read("ca.pem")));
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let service = CountingServer::default();
+    let received = Arc::clone(&service.received);
+
+    tokio::spawn(
+        Server::builder()
+            .tls_config(tls)
+            .unwrap()
+            .add_service(UploadServiceServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+
+    let uri = format!("https://127.0.0.1:{}", port).parse().unwrap();
+    (uri, received)
+}
+
+fn segment_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "screen_record_tls_{}_{}.webm",
+        std::process::id(),
+        name
+    ));
+    std::fs::write(&path, vec![7u8; FILE_SIZE]).unwrap();
+    path
+}
+
+fn uploader(endpoint: Uri, tls: GrpcTls) -> Uploader {
+    Uploader::new(
+        endpoint,
+        UploadOptions {
+            chunk_size: 64 * 1024,
+            tls: Some(tls),
+            ..UploadOptions::default()
+        },
+    )
+    .unwrap()
+}
+
+fn trusting_test_ca() -> GrpcTls {
+    GrpcTls {
+        ca_cert: Some(cert("ca.pem")),
+        server_name: Some("upload.test".into()),
+        client_identity: None,
+    }
+}
+
+#[tokio::test]
+async fn uploads_over_tls_with_custom_ca_and_server_name() {
+    let (endpoint, received) = start_server(false).await;
+    let path = segment_file("tls");
+
+    let result = uploader(endpoint, trusting_test_ca())
+        .upload(&path, |_, _| {})
+        .await;
+    std::fs::remove_file(&path).unwrap();
+
+    result.unwrap();
+    assert_eq!(received.load(Ordering::SeqCst), FILE_SIZE as u64);
+}
+
+#[tokio::test]
+async fn rejects_server_signed_by_unknown_ca() {
+    let (endpoint, received) = start_server(false).await;
+    let path = segment_file("unknown_ca");
+
+    let tls = GrpcTls {
+        ca_cert: None,
+        ..trusting_test_ca()
+    };
+    let result = uploader(endpoint, tls).upload(&path, |_, _| {}).await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(result.is_err());
+    assert_eq!(received.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn rejects_certificate_for_another_name() {
+    let (endpoint, received) = start_server(false).await;
+    let path = segment_file("wrong_name");
+
+    // Without the override the certificate is checked against 127.0.0.1
+    let tls = GrpcTls {
+        server_name: None,
+        ..trusting_test_ca()
+    };
+    let result = uploader(endpoint, tls).upload(&path, |_, _| {}).await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(result.is_err());
+    assert_eq!(received.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn mutual_tls_requires_a_client_certificate() {
+    let (endpoint, received) = start_server(true).await;
+    let path = segment_file("mtls");
+
+    let anonymous = uploader(endpoint.clone(), trusting_test_ca())
+        .upload(&path, |_, _| {})
+        .await;
+
+    let tls = GrpcTls {
+        client_identity: Some((cert("client.pem"), cert("client.key"))),
+        ..trusting_test_ca()
+    };
+    let authenticated = uploader(endpoint, tls).upload(&path, |_, _| {}).await;
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(anonymous.is_err());
+    authenticated.unwrap();
+    assert_eq!(received.load(Ordering::SeqCst), FILE_SIZE as u64);
+}
+
+#[test]
+fn missing_certificate_file_is_reported() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let _guard = runtime.enter();
+
+    let tls = GrpcTls {
+        ca_cert: Some(cert("missing.pem")),
+        ..GrpcTls::default()
+    };
+    let result = Uploader::new(
+        "https://127.0.0.1:1".parse().unwrap(),
+        UploadOptions {
+            tls: Some(tls),
+            ..UploadOptions::default()
+        },
+    );
+    assert!(result.is_err());
+}
@@ -0,0 +1,254 @@
+//! The native recording pipeline without a display: synthetic capture
+//! sources, and a test-pattern segment recorded, encoded, uploaded to a
+//! local gRPC server and reported to a local API. The end-to-end test needs
+//! ffmpeg and is skipped when it cannot be run.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use screen_record::config::{CaptureSourceKind, RecorderBackend, RecordingConfig, Resolution};
+use screen_record::modules::api::grpc_upload::proto::upload_request::Type;
+use screen_record::modules::api::grpc_upload::proto::upload_service_server::{
+    UploadService, UploadServiceServer,
+};
+use screen_record::modules::api::grpc_upload::proto::{
+    QueryOffsetRequest, QueryOffsetResponse, UploadRequest, UploadResponse,
+};
+use screen_record::modules::components::record_screen::capture_source_fl::{
+    CaptureSource, RawFileSource, TestPattern,
+};
+use screen_record::modules::components::video_conversion::video_conversion_fl::ffmpeg_path;
+use screen_record::modules::components::video_conversion::video_encoder_fl::FrameEncoder;
+use screen_record::run::RecordingSession;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+/// The milliseconds a [`TestPattern`] frame is stamped with.
+fn stamp(frame: &[u8], width: usize) -> u32 {
+    (0..32).fold(0, |stamp, bit| {
+        // Sample the middle of each 8x8 square
+        let offset = (4 * width + bit * 8 + 4) * 4;
+        (stamp << 1) | u32::from(frame[offset] == 0xff)
+    })
+}
+
+#[test]
+fn test_pattern_is_stamped_with_the_capture_time() {
+    let mut pattern = TestPattern::new(320, 24);
+    assert_eq!((pattern.width(), pattern.height()), (320, 24));
+
+    let first = pattern.frame().unwrap().to_vec();
+    std::thread::sleep(Duration::from_millis(50));
+    let second = pattern.frame().unwrap().to_vec();
+
+    assert_eq!(first.len(), 320 * 24 * 4);
+    let elapsed = stamp(&second, 320) - stamp(&first, 320);
+    assert!(
+        (50..1_000).contains(&elapsed),
+        "{} ms between frames",
+        elapsed
+    );
+    assert_ne!(first, second);
+}
+
+#[test]
+fn test_pattern_narrower_than_its_stamp_is_cropped() {
+    // The 32 stamp bits span 256 pixels
+    let mut pattern = TestPattern::new(64, 48);
+    let frame = pattern.frame().unwrap().to_vec();
+    assert_eq!(frame.len(), 64 * 48 * 4);
+
+    let mut tiny = TestPattern::new(1, 1);
+    assert_eq!(tiny.frame().unwrap().len(), 4);
+}
+
+#[test]
+fn raw_file_source_replays_frames_and_loops() {
+    let path =
+        std::env::temp_dir().join(format!("screen_record_headless_{}.raw", std::process::id()));
+    let frames: Vec<Vec<u8>> = (1..=2u8).map(|n| vec![n; 4 * 2 * 4]).collect();
+    std::fs::write(&path, frames.concat()).unwrap();
+
+    let mut source = RawFileSource::open(&path, 4, 2).unwrap();
+    let replayed: Vec<Vec<u8>> = (0..3).map(|_| source.frame().unwrap().to_vec()).collect();
+
+    assert_eq!(
+        replayed,
+        vec![frames[0].clone(), frames[1].clone(), frames[0].clone()]
+    );
+    assert!(RawFileSource::open(&path, 64, 64).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[derive(Default)]
+struct CountingServer {
+    received: Arc<AtomicU64>,
+}
+
+#[tonic::async_trait]
+impl UploadService for CountingServer {
+    async fn upload_file(
+        &self,
+        request: Request<Streaming<UploadRequest>>,
+    ) -> Result<Response<UploadResponse>, Status> {
+        let mut stream = request.into_inner();
+        let mut received = 0;
+        while let Some(message) = stream.message().await? {
+            if let Some(Type::Data(chunk)) = message.r#type {
+                received += chunk.data.len() as u64;
+            }
+        }
+        self.received.store(received, Ordering::SeqCst);
+        Ok(Response::new(UploadResponse {
+            message: "stored".into(),
+            received,
+        }))
+    }
+
+    async fn query_offset(
+        &self,
+        _request: Request<QueryOffsetRequest>,
+    ) -> Result<Response<QueryOffsetResponse>, Status> {
+        Ok(Response::new(QueryOffsetResponse { offset: 0 }))
+    }
+}
+
+async fn start_grpc_server() -> (String, Arc<AtomicU64>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let service = CountingServer::default();
+    let received = Arc::clone(&service.received);
+
+    tokio::spawn(
+        Server::builder()
+            .add_service(UploadServiceServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    (endpoint, received)
+}
+
+/// API URL and the body of every request it received.
+async fn start_api() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/api", listener.local_addr().unwrap());
+    let bodies = Arc::new(Mutex::new(Vec::new()));
+
+    let seen = Arc::clone(&bodies);
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            // Read until the body announced by Content-Length is complete
+            loop {
+                let read = stream.read(&mut buffer).await.unwrap_or(0);
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| {
+                            let line = line.to_ascii_lowercase();
+                            line.strip_prefix("content-length:")?.trim().parse().ok()
+                        })
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        seen.lock().unwrap().push(body.to_string());
+                        break;
+                    }
+                }
+            }
+            let _ = stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .await;
+        }
+    });
+    (url, bodies)
+}
+
+fn ffmpeg_available() -> bool {
+    std::process::Command::new(ffmpeg_path())
+        .arg("-version")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+#[test]
+fn encoder_accepts_odd_frame_sizes() {
+    if !ffmpeg_available() {
+        eprintln!("skipping: ffmpeg is not available");
+        return;
+    }
+    let path = std::env::temp_dir().join(format!("screen_record_odd_{}.mp4", std::process::id()));
+    let mut pattern = TestPattern::new(321, 241);
+    let mut encoder = FrameEncoder::spawn(&ffmpeg_path(), &path, 321, 241, 10).unwrap();
+    for _ in 0..5 {
+        encoder.write_frame(&pattern.frame().unwrap()).unwrap();
+    }
+
+    assert_eq!(encoder.finish().unwrap(), 5);
+    assert!(std::fs::metadata(&path).unwrap().len() > 0);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn records_uploads_and_notifies_without_a_display() {
+    if !ffmpeg_available() {
+        eprintln!("skipping: ffmpeg is not available");
+        return;
+    }
+    // The app directory is the first of LOCALAPPDATA and HOME that is set
+    let app_root: PathBuf =
+        std::env::temp_dir().join(format!("screen_record_headless_{}", std::process::id()));
+    std::env::set_var("LOCALAPPDATA", &app_root);
+
+    let (endpoint, received) = start_grpc_server().await;
+    let (api_url, bodies) = start_api().await;
+    let config = RecordingConfig::builder()
+        .user_id("headless")
+        .api_url(api_url)
+        .grpc_endpoint(endpoint)
+        .proxy("none")
+        .recorder_backend(RecorderBackend::Native)
+        .capture_source(CaptureSourceKind::TestPattern)
+        .resolution(Resolution::new(320, 240).unwrap())
+        .fps(10)
+        .segment_duration(Duration::from_secs(2))
+        .build()
+        .unwrap();
+
+    RecordingSession::new(config)
+        .unwrap()
+        .record_segment()
+        .await
+        .unwrap();
+
+    assert!(received.load(Ordering::SeqCst) > 0);
+    let bodies = bodies.lock().unwrap();
+    assert_eq!(bodies.len(), 1);
+    assert!(
+        bodies[0].contains("\"employeeId\":\"headless\""),
+        "{}",
+        bodies[0]
+    );
+    assert!(bodies[0].contains(".mp4\""), "{}", bodies[0]);
+    // The uploaded segment is gone from the temp directory
+    let temp = app_root.join("screen_record").join("temp");
+    let left: Vec<_> = std::fs::read_dir(&temp)
+        .unwrap()
+        .flatten()
+        .map(|e| e.path())
+        .collect();
+    assert!(left.is_empty(), "{:?}", left);
+    let _ = std::fs::remove_dir_all(&app_root);
+}
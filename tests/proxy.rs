@@ -0,0 +1,300 @@
+//! Downloads and gRPC uploads through a local proxy that requires basic
+//! auth. The proxy serves plain requests itself and tunnels `CONNECT`.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use screen_record::config::GrpcTls;
+use screen_record::events::EventSender;
+use screen_record::modules::api::download::{download_client, download_recorder_exe, RetryPolicy};
+use screen_record::modules::api::grpc_upload::proto::upload_request::Type;
+use screen_record::modules::api::grpc_upload::proto::upload_service_server::{
+    UploadService, UploadServiceServer,
+};
+use screen_record::modules::api::grpc_upload::proto::{
+    QueryOffsetRequest, QueryOffsetResponse, UploadRequest, UploadResponse,
+};
+use screen_record::modules::api::grpc_upload::{UploadOptions, Uploader};
+use screen_record::proxy::{ProxyConfig, ProxyMode};
+use screen_record::tls::TlsPolicy;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::{Identity, Server, ServerTlsConfig, Uri};
+use tonic::{Request, Response, Status, Streaming};
+use url::Url;
+
+const BODY: &[u8] = b"recorder bytes served by the proxy";
+const USERNAME: &str = "agent";
+const PASSWORD: &str = "s3cret:pass";
+
+/// Proxy URL and the head of every request it received.
+async fn start_proxy() -> (Url, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let heads = Arc::new(Mutex::new(Vec::new()));
+
+    let seen = Arc::clone(&heads);
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(serve(stream, Arc::clone(&seen)));
+        }
+    });
+    (url, heads)
+}
+
+async fn serve(mut stream: TcpStream, seen: Arc<Mutex<Vec<String>>>) {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if stream.read(&mut byte).await.unwrap_or(0) == 0 {
+            return;
+        }
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8_lossy(&head).into_owned();
+    seen.lock().unwrap().push(head.clone());
+
+    let request_line = head.lines().next().unwrap_or_default().to_string();
+    let proxied = request_line.starts_with("CONNECT ") || request_line.contains(" http://");
+    let token = BASE64.encode(format!("{}:{}", USERNAME, PASSWORD));
+    let authorized = head
+        .lines()
+        .any(|line| line.eq_ignore_ascii_case(&format!("proxy-authorization: Basic {}", token)));
+    if proxied && !authorized {
+        let _ = stream
+            .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\nContent-Length: 0\r\n\r\n")
+            .await;
+        return;
+    }
+
+    if let Some(target) = request_line.strip_prefix("CONNECT ") {
+        let target = target.split_whitespace().next().unwrap_or_default();
+        let mut upstream = TcpStream::connect(target).await.unwrap();
+        stream
+            .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .await
+            .unwrap();
+        let _ = tokio::io::copy_bidirectional(&mut stream, &mut upstream).await;
+        return;
+    }
+
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        BODY.len()
+    );
+    stream.write_all(response.as_bytes()).await.unwrap();
+    stream.write_all(BODY).await.unwrap();
+    let _ = stream.shutdown().await;
+}
+
+fn through(proxy: &Url, password: &str) -> ProxyConfig {
+    ProxyConfig {
+        mode: ProxyMode::Url(proxy.clone()),
+        no_proxy: Vec::new(),
+        auth: Some((USERNAME.into(), password.into())),
+    }
+}
+
+fn temp_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "screen_record_proxy_{}_{}",
+        std::process::id(),
+        name
+    ));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+async fn download(proxy: &ProxyConfig, url: &str, part: &Path) -> Result<u64, String> {
+    let client = download_client(&TlsPolicy::default(), proxy).unwrap();
+    download_recorder_exe(
+        &client,
+        &[url],
+        part,
+        &RetryPolicy::default(),
+        &EventSender::new(),
+    )
+    .await
+    .map_err(|e| format!("{:?}", e.attempts))
+}
+
+#[tokio::test]
+async fn downloads_through_an_authenticating_proxy() {
+    let (proxy, heads) = start_proxy().await;
+    let part = temp_file("download.part");
+    let url = "http://recorder.invalid/screen_record.exe";
+
+    let result = download(&through(&proxy, PASSWORD), url, &part).await;
+
+    assert_eq!(result.unwrap(), BODY.len() as u64);
+    assert_eq!(std::fs::read(&part).unwrap(), BODY);
+    let heads = heads.lock().unwrap();
+    assert!(
+        heads[0].starts_with(&format!("GET {} HTTP/1.1", url)),
+        "{}",
+        heads[0]
+    );
+    std::fs::remove_file(&part).unwrap();
+}
+
+#[tokio::test]
+async fn wrong_proxy_password_fails_the_download() {
+    let (proxy, _) = start_proxy().await;
+    let part = temp_file("rejected.part");
+
+    let result = download(
+        &through(&proxy, "wrong"),
+        "http://recorder.invalid/screen_record.exe",
+        &part,
+    )
+    .await;
+
+    assert!(result.unwrap_err().contains("407"));
+}
+
+#[tokio::test]
+async fn no_proxy_hosts_are_reached_directly() {
+    // The proxy doubles as the origin server here
+    let (proxy, heads) = start_proxy().await;
+    let part = temp_file("direct.part");
+    let config = ProxyConfig {
+        no_proxy: vec!["127.0.0.1".into()],
+        ..through(&proxy, PASSWORD)
+    };
+
+    let result = download(
+        &config,
+        proxy.join("screen_record.exe").unwrap().as_str(),
+        &part,
+    )
+    .await;
+
+    assert_eq!(result.unwrap(), BODY.len() as u64);
+    let heads = heads.lock().unwrap();
+    assert!(
+        heads[0].starts_with("GET /screen_record.exe "),
+        "{}",
+        heads[0]
+    );
+    assert!(!heads[0]
+        .to_ascii_lowercase()
+        .contains("proxy-authorization"));
+    std::fs::remove_file(&part).unwrap();
+}
+
+#[derive(Default)]
+struct CountingServer {
+    received: Arc<AtomicU64>,
+}
+
+#[tonic::async_trait]
+impl UploadService for CountingServer {
+    async fn upload_file(
+        &self,
+        request: Request<Streaming<UploadRequest>>,
+    ) -> Result<Response<UploadResponse>, Status> {
+        let mut stream = request.into_inner();
+        let mut received = 0;
+        while let Some(message) = stream.message().await? {
+            if let Some(Type::Data(chunk)) = message.r#type {
+                received += chunk.data.len() as u64;
+            }
+        }
+        self.received.store(received, Ordering::SeqCst);
+        Ok(Response::new(UploadResponse {
+            message: "stored".into(),
+            received,
+        }))
+    }
+
+    async fn query_offset(
+        &self,
+        _request: Request<QueryOffsetRequest>,
+    ) -> Result<Response<QueryOffsetResponse>, Status> {
+        Ok(Response::new(QueryOffsetResponse { offset: 0 }))
+    }
+}
+
+fn cert(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/certs")
+        .join(name)
+}
+
+async fn start_grpc_server() -> (Uri, Arc<AtomicU64>) {
+    let identity = Identity::from_pem(
+        std::fs::read(cert("server.pem")).unwrap(),
+        std::fs::read(cert("server.key")).unwrap(),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let service = CountingServer::default();
+    let received = Arc::clone(&service.received);
+
+    tokio::spawn(
+        Server::builder()
+            .tls_config(ServerTlsConfig::new().identity(identity))
+            .unwrap()
+            .add_service(UploadServiceServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    (
+        format!("https://127.0.0.1:{}", port).parse().unwrap(),
+        received,
+    )
+}
+
+async fn upload_through(name: &str, proxy: ProxyConfig) -> (Result<(), String>, u64) {
+    let (endpoint, received) = start_grpc_server().await;
+    let path = temp_file(&format!("{}.webm", name));
+    std::fs::write(&path, vec![7u8; 200_000]).unwrap();
+
+    let uploader = Uploader::new(
+        endpoint,
+        UploadOptions {
+            tls: Some(GrpcTls {
+                ca_cert: Some(cert("ca.pem")),
+                server_name: Some("upload.test".into()),
+                client_identity: None,
+            }),
+            proxy,
+            ..UploadOptions::default()
+        },
+    )
+    .unwrap();
+    let result = uploader.upload(&path, |_, _| {}).await;
+    std::fs::remove_file(&path).unwrap();
+    (
+        result.map_err(|e| e.to_string()),
+        received.load(Ordering::SeqCst),
+    )
+}
+
+#[tokio::test]
+async fn uploads_over_tls_through_a_connect_tunnel() {
+    let (proxy, heads) = start_proxy().await;
+
+    let (result, received) = upload_through("tunnel", through(&proxy, PASSWORD)).await;
+
+    result.unwrap();
+    assert_eq!(received, 200_000);
+    let heads = heads.lock().unwrap();
+    assert!(heads[0].starts_with("CONNECT 127.0.0.1:"), "{}", heads[0]);
+}
+
+#[tokio::test]
+async fn rejected_connect_fails_the_upload() {
+    let (proxy, _) = start_proxy().await;
+
+    let (result, received) = upload_through("rejected", through(&proxy, "wrong")).await;
+
+    assert!(result.is_err());
+    assert_eq!(received, 0);
+}
@@ -0,0 +1,127 @@
+//! The on-disk upload queue: one process at a time, and recordings that may
+//! still be written are left alone.
+
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use screen_record::queue::{UploadQueue, UploadState};
+
+const SETTLE: Duration = Duration::from_secs(120);
+
+/// A fresh app directory with an empty `temp` inside.
+fn app_dir(name: &str) -> (PathBuf, PathBuf) {
+    let app = std::env::temp_dir().join(format!(
+        "screen_record_queue_{}_{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&app);
+    let tmp = app.join("temp");
+    fs::create_dir_all(&tmp).unwrap();
+    (app, tmp)
+}
+
+fn video(tmp: &std::path::Path, name: &str, age: Duration) -> PathBuf {
+    let path = tmp.join(name);
+    fs::write(&path, vec![1u8; 2048]).unwrap();
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(SystemTime::now() - age)
+        .unwrap();
+    path
+}
+
+#[test]
+fn only_one_process_can_open_the_queue() {
+    let (app, tmp) = app_dir("lock");
+
+    let queue = UploadQueue::open(&app, &tmp, SETTLE).unwrap();
+    let second = UploadQueue::open(&app, &tmp, SETTLE).unwrap_err();
+    assert_eq!(second.kind(), ErrorKind::WouldBlock);
+
+    drop(queue);
+    UploadQueue::open(&app, &tmp, SETTLE).unwrap();
+    fs::remove_dir_all(&app).unwrap();
+}
+
+#[test]
+fn recordings_younger_than_a_segment_are_not_adopted() {
+    let (app, tmp) = app_dir("adopt");
+    let old = video(&tmp, "user20250101T000000.mp4", SETTLE * 2);
+    video(&tmp, "user20250101T000200.mp4", Duration::ZERO);
+
+    let queue = UploadQueue::open(&app, &tmp, SETTLE).unwrap();
+    let paths: Vec<_> = queue.entries().iter().map(|e| e.path.clone()).collect();
+    assert_eq!(paths, vec![old]);
+    fs::remove_dir_all(&app).unwrap();
+}
+
+#[test]
+fn enqueue_keeps_the_state_of_a_tracked_segment() {
+    let (app, tmp) = app_dir("enqueue");
+    let path = video(&tmp, "user20250101T000000.mp4", SETTLE * 2);
+
+    let mut queue = UploadQueue::open(&app, &tmp, SETTLE).unwrap();
+    let claimed = queue.claim_due().unwrap().unwrap();
+    queue.mark_uploaded(&claimed.id).unwrap();
+
+    queue.enqueue(&path).unwrap();
+    let entries = queue.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].state, UploadState::Uploaded);
+    fs::remove_dir_all(&app).unwrap();
+}
+
+#[test]
+fn notified_segment_is_not_adopted_again_while_its_file_remains() {
+    let (app, tmp) = app_dir("notified");
+    let path = video(&tmp, "user20250101T000000.mp4", SETTLE * 2);
+
+    let mut queue = UploadQueue::open(&app, &tmp, SETTLE).unwrap();
+    let claimed = queue.claim_due().unwrap().unwrap();
+    queue.mark_uploaded(&claimed.id).unwrap();
+    queue.mark_notified(&claimed.id).unwrap();
+    // The delete after the notification failed
+    assert_eq!(queue.adopt_untracked().unwrap(), 0);
+    assert!(queue.entries().is_empty());
+    drop(queue);
+
+    // Reopening deletes the file instead of queueing it again
+    let queue = UploadQueue::open(&app, &tmp, SETTLE).unwrap();
+    assert!(queue.entries().is_empty());
+    assert!(!path.exists());
+    fs::remove_dir_all(&app).unwrap();
+}
+
+#[test]
+fn track_files_are_not_adopted_as_segments() {
+    let (app, tmp) = app_dir("tracks");
+    video(&tmp, "user20250101T000000.0.mp4", SETTLE * 2);
+    video(&tmp, "user20250101T000000.DISPLAY.2.mp4", SETTLE * 2);
+    let segment = video(&tmp, "first.last20250101T000200.mp4", SETTLE * 2);
+
+    let queue = UploadQueue::open(&app, &tmp, SETTLE).unwrap();
+    let paths: Vec<_> = queue.entries().iter().map(|e| e.path.clone()).collect();
+    assert_eq!(paths, vec![segment]);
+    fs::remove_dir_all(&app).unwrap();
+}
+
+#[test]
+fn released_segment_can_be_claimed_again() {
+    let (app, tmp) = app_dir("release");
+    video(&tmp, "user20250101T000000.mp4", SETTLE * 2);
+
+    let mut queue = UploadQueue::open(&app, &tmp, SETTLE).unwrap();
+    let claimed = queue.claim_due().unwrap().unwrap();
+    assert!(queue.claim_due().unwrap().is_none());
+
+    queue.release(&claimed.id);
+    let again = queue.claim_due().unwrap().unwrap();
+    assert_eq!(again.id, claimed.id);
+    assert_eq!(again.state, UploadState::Uploading);
+    fs::remove_dir_all(&app).unwrap();
+}
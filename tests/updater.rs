@@ -0,0 +1,29 @@
+//! Installed recorder versions are changed by one process at a time.
+
+use std::fs::{self, File};
+use std::io::ErrorKind;
+
+use screen_record::error::ScreenRecordError;
+use screen_record::updater::{self, LOCK_FILE};
+
+#[test]
+fn rollback_refuses_while_another_process_holds_the_lock() {
+    let bin = std::env::temp_dir().join(format!("screen_record_updater_{}", std::process::id()));
+    let _ = fs::remove_dir_all(&bin);
+    fs::create_dir_all(&bin).unwrap();
+
+    // Another process is updating the recorder
+    let installing = File::create(bin.join(LOCK_FILE)).unwrap();
+    installing.lock().unwrap();
+    match updater::rollback(&bin) {
+        Err(ScreenRecordError::Io(e)) => assert_eq!(e.kind(), ErrorKind::WouldBlock),
+        other => panic!("{:?}", other),
+    }
+
+    drop(installing);
+    assert!(matches!(
+        updater::rollback(&bin),
+        Err(ScreenRecordError::NoPreviousRecorder)
+    ));
+    fs::remove_dir_all(&bin).unwrap();
+}